@@ -1,12 +1,15 @@
 #![allow(non_snake_case)]
 
 use std::{
-    collections::HashSet,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
     env,
     error::Error,
+    fs,
     fmt::{self, Debug, Display, Formatter},
-    io::stdin,
-    thread, time::Duration
+    io::{stdin, stdout, Write},
+    ops::Range,
+    thread, time::{Duration, SystemTime, UNIX_EPOCH}
 };
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -43,13 +46,52 @@ macro_rules! IF {
     ($p:expr, $t:expr, $f:expr) => (if $p { $t } else { $f })
 }
 
+// Rng ///////////////////////////////////////////////////////////////
+
+// SplitMix64.  Small, fast and the same sequence on every machine, so a
+// seed always reproduces the same map.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new (seed: u64) -> Rng { Rng{state: seed} }
+    pub fn next_u64 (&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    // Uniform in 0..n (rejection sampling avoids modulo bias)
+    pub fn below (&mut self, n: usize) -> usize {
+        assert!(0 < n, "empty range");
+        let n = n as u64;
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let r = self.next_u64();
+            if r < zone { return (r % n) as usize }
+        }
+    }
+    // Uniform in [0,1)
+    pub fn unit (&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn clockseed () -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
 
 // Terminal //////////////////////////////////////////////////////////
 
 #[derive(Debug)]
 struct Term {
     h:usize,
-    w:usize
+    w:usize,
+    plot:bool // Draw cells as they change
 }
 
 impl Term {
@@ -57,31 +99,77 @@ impl Term {
         let mut args = env::args().skip(1).take(2).flat_map(|s| s.parse::<usize>());
         Term {
             h: args.next().unwrap_or(25),
-            w: args.next().unwrap_or(80)
+            w: args.next().unwrap_or(80),
+            plot: true
         }
     }
 }
 
 // Point //////////////////////////////////////////////////////////
 
-#[derive(Eq, Clone, Hash, PartialEq)]
+#[derive(Eq, Clone, Hash, PartialEq, PartialOrd, Ord)]
 pub struct Point {
     y: usize,
-    x: usize
+    x: usize,
+    z: usize // Layer, 0 unless the grid has several
 }
 
 impl Point {
-    fn new (y: usize, x: usize) -> Point { Point{y, x} }
+    fn new (y: usize, x: usize) -> Point { Point{y, x, z: 0} }
+    pub fn voxel (y: usize, x: usize, z: usize) -> Point { Point{y, x, z} }
 }
 
 impl Debug for Point {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if 0 < self.z { return fmt.write_str(&format!("({},{},{})", self.y, self.x, self.z)) }
         fmt.write_str(&format!("({},{})", self.y, self.x))
     }
 }
 
+// Set of points with O(1) insert/remove and a uniform random pick.  Unlike
+// HashSet the order never depends on the per-process hash seed.
+pub struct PointSet {
+    points: Vec<Point>,
+    index: HashMap<Point, usize>
+}
+
+impl PointSet {
+    fn new () -> PointSet { PointSet{points: Vec::new(), index: HashMap::new()} }
+    fn len (&self) -> usize { self.points.len() }
+    fn insert (&mut self, p: Point) -> bool {
+        if self.index.contains_key(&p) { return false }
+        self.index.insert(p.clone(), self.points.len());
+        self.points.push(p);
+        true
+    }
+    fn remove (&mut self, p: &Point) -> bool {
+        match self.index.remove(p) {
+            Some(i) => {
+                self.points.swap_remove(i);
+                if i < self.points.len() { self.index.insert(self.points[i].clone(), i); }
+                true
+            }
+            None => false
+        }
+    }
+    // Remove and return a uniformly chosen point
+    fn pick (&mut self, rng: &mut Rng) -> Option<Point> {
+        if self.points.is_empty() { return None }
+        let p = self.points[rng.below(self.points.len())].clone();
+        self.remove(&p);
+        Some(p)
+    }
+}
+
+impl Debug for PointSet {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(&format!("{:?}", self.points))
+    }
+}
+
 ////////////////////////////////////////
 
+#[derive(Clone)]
 pub struct Glyph {
     color: String,
     glyph: String
@@ -102,7 +190,9 @@ impl Glyph {
 pub struct State {
     pub id: usize,
     glyph: Glyph,
-    projections: Vec<SuperState> // Superstates allowed for each direction
+    weight: f64, // Relative likelihood when collapsing, default 1
+    projections: Vec<SuperState>, // Superstates allowed for each direction
+    reach: [usize; 4] // Cells of its big tile past this one up, down, right, left
 }
 
 impl State {
@@ -110,179 +200,1696 @@ impl State {
         State{
             id,
             glyph: Glyph::new(clr.to_string(), glf.to_string()),
+            weight: 1.0,
             projections: projections.iter()
-                .map(|states| SuperState::from(states.iter().map(|i|*i)))
-                .collect()
+                .map(|states| SuperState::from(states.iter().copied()))
+                .collect(),
+            reach: [0; 4]
+        }
+    }
+    // Rules for the diagonal neighbors, after the four of State::new:  up
+    // right, down left, up left, down right.  For the Moore topology.
+    fn diagonals(mut self, projections: &[&[usize]]) -> State {
+        assert!(4 == self.projections.len() && 4 == projections.len(), "four projections and four diagonals");
+        self.projections.extend(projections.iter().map(|states| SuperState::from(states.iter().copied())));
+        self
+    }
+    // State::new(..).weighted(10.0) is ten times as likely as a weight 1 state
+    fn weighted(mut self, weight: f64) -> State {
+        assert!(0.0 <= weight && weight.is_finite(), "weight must be finite and non-negative");
+        self.weight = weight;
+        self
+    }
+}
+
+// Symmetry //////////////////////////////////////////////////////////
+
+// How a tile repeats under turning and mirroring, named after a letter
+// with the same symmetry.  Variant 0 is the tile as declared and each
+// variant after it is the one before turned a quarter clockwise, so the
+// declared tile must be drawn the way its class expects:
+//   X  1 variant, the same every way        +
+//   I  2, symmetric left to right           |  -
+//   \  2, mirror image is its own turn      \  /
+//   T  4, symmetric left to right           ┴ ├ ┬ ┤
+//   L  4, mirror image is its own turn      ┘ └ ┌ ┐
+//   F  8, no symmetry.  4..8 are the mirror images of 0..4
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symmetry { X, I, Backslash, T, L, F }
+
+impl Symmetry {
+    pub fn cardinality (&self) -> usize {
+        match self {
+            Symmetry::X => 1,
+            Symmetry::I | Symmetry::Backslash => 2,
+            Symmetry::T | Symmetry::L => 4,
+            Symmetry::F => 8
+        }
+    }
+    // Variant v turned a quarter clockwise
+    fn turn (&self, v: usize) -> usize {
+        match self {
+            Symmetry::X => 0,
+            Symmetry::I | Symmetry::Backslash => 1 - v,
+            Symmetry::T | Symmetry::L => (v + 1) % 4,
+            Symmetry::F => IF!(v < 4, (v + 1) % 4, 4 + (v + 3) % 4)
+        }
+    }
+    // Variant v mirrored left to right
+    fn mirror (&self, v: usize) -> usize {
+        match self {
+            Symmetry::X | Symmetry::I => v,
+            Symmetry::Backslash => 1 - v,
+            Symmetry::T => (4 - v) % 4,
+            Symmetry::L => v ^ 1,
+            Symmetry::F => (v + 4) % 8
         }
     }
 }
 
+// Directions turned a quarter clockwise and mirrored left to right
+const TURN: [usize; 4] = [2, 3, 1, 0];
+const MIRROR: [usize; 4] = [0, 1, 3, 2];
+
+// A tile declared once and expanded into a State per variant by variants()
+pub struct Tile {
+    glyphs: Vec<Glyph>, // One for every variant, or one each
+    symmetry: Symmetry,
+    weight: f64,
+    projections: Vec<Vec<(usize, usize)>> // (tile, variant) allowed in each direction of variant 0
+}
+
+impl Tile {
+    pub fn new (glyphs: &[(&str, &str)], symmetry: Symmetry, projections: &[&[(usize, usize)]]) -> Tile {
+        assert!(glyphs.len() == 1 || glyphs.len() == symmetry.cardinality(), "one glyph, or one per variant");
+        assert!(4 == projections.len(), "one projection per direction");
+        Tile {
+            glyphs: glyphs.iter().map(|(clr, glf)| Glyph::new(clr.to_string(), glf.to_string())).collect(),
+            symmetry,
+            weight: 1.0,
+            projections: projections.iter().map(|p| p.to_vec()).collect()
+        }
+    }
+    // Weight of each of its variants
+    pub fn weighted (mut self, weight: f64) -> Tile {
+        assert!(0.0 <= weight && weight.is_finite(), "weight must be finite and non-negative");
+        self.weight = weight;
+        self
+    }
+}
+
+// Every variant of every tile as a State.  Tile t's variant v is state
+// v plus the variant counts of the tiles before t.  A variant allows what
+// its tile's declared neighbors become under the same turns and mirroring,
+// so a side that follows from the tile's symmetry may be left empty.
+pub fn variants (tiles: &[Tile]) -> Vec<State> {
+    let offsets: Vec<usize> = tiles.iter()
+        .scan(0, |n, t| { let o = *n; *n += t.symmetry.cardinality(); Some(o) })
+        .collect();
+    let count = tiles.iter().map(|t| t.symmetry.cardinality()).sum();
+    let mut projections = vec![vec![SuperState::default(); 4]; count];
+    // The 8 turns and mirrorings: g%4 quarter turns, then a mirror when 4 <= g
+    let apply = |g: usize, f: &dyn Fn(usize, bool) -> usize, v: usize| {
+        let v = (0..g % 4).fold(v, |v, _| f(v, false));
+        IF!(4 <= g, f(v, true), v)
+    };
+    tiles.iter().enumerate().for_each(|(t, tile)| (0..8).for_each(|g| {
+        let variant = |u: usize, v: usize| {
+            let sym = tiles[u].symmetry;
+            offsets[u] + apply(g, &|v, m| IF!(m, sym.mirror(v), sym.turn(v)), v)
+        };
+        let id = variant(t, 0);
+        tile.projections.iter().enumerate().for_each(|(dir, neighbors)| {
+            let dir = apply(g, &|d, m| IF!(m, MIRROR[d], TURN[d]), dir);
+            neighbors.iter().for_each(|(u, v)| projections[id][dir].insert(variant(*u, *v)));
+        });
+    }));
+    tiles.iter().enumerate().flat_map(|(t, tile)| (0..tile.symmetry.cardinality()).map(move |v| (t, tile, v)))
+        .zip(projections)
+        .map(|((t, tile, v), projections)| State {
+            id: offsets[t] + v,
+            glyph: tile.glyphs[IF!(1 == tile.glyphs.len(), 0, v)].clone(),
+            weight: tile.weight,
+            projections,
+            reach: [0; 4]
+        })
+        .collect()
+}
+
+// Big tiles /////////////////////////////////////////////////////////
+
+// A tile covering several cells, say a 5x5 room, drawn a character per
+// cell.  Each side allows the states listed for it, up, down, right and
+// left, and openings let some cells along a side allow more.
+pub struct BigTile {
+    color: String,
+    rows: Vec<Vec<char>>,
+    weight: f64,
+    sides: Vec<Vec<Vec<usize>>> // Per side, the states allowed past each cell along it
+}
+
+impl BigTile {
+    pub fn new (color: &str, rows: &[&str], sides: &[&[usize]]) -> BigTile {
+        let rows: Vec<Vec<char>> = rows.iter().map(|r| r.chars().collect()).collect();
+        let (h, w) = (rows.len(), rows.first().map_or(0, |r| r.len()));
+        assert!(0 < h && 0 < w && rows.iter().all(|r| r.len() == w), "a rectangle of characters");
+        assert!(4 == sides.len(), "one list per side");
+        let sides = sides.iter().enumerate().map(|(dir, side)| vec![side.to_vec(); IF!(dir < 2, w, h)]).collect();
+        BigTile{color: color.to_string(), rows, weight: 1.0, sides}
+    }
+    // Let these cells along side dir, counted from the top or left, allow
+    // states too, say a passage through a wall
+    pub fn opening (mut self, dir: usize, cells: Range<usize>, states: &[usize]) -> BigTile {
+        assert!(cells.end <= self.sides[dir].len(), "opening past the end of the side");
+        self.sides[dir][cells].iter_mut().for_each(|side| side.extend_from_slice(states));
+        self
+    }
+    // Weight of each of its cells
+    pub fn weighted (mut self, weight: f64) -> BigTile {
+        assert!(0.0 <= weight && weight.is_finite(), "weight must be finite and non-negative");
+        self.weight = weight;
+        self
+    }
+}
+
+// A tileset with big tiles added after its states, a state per cell, row
+// by row.  A cell allows only its own neighbors inside the tile, so
+// collapsing any one of them places the whole tile and propagation goes
+// on from all of its edges.  The states a side lists are made to allow it
+// back.  Tiles never hang past an edge that doesn't wrap.  Square grids.
+pub fn bigTiles (mut states: Vec<State>, tiles: &[BigTile]) -> Vec<State> {
+    tiles.iter().for_each(|tile| {
+        let (h, w) = (tile.rows.len(), tile.rows[0].len());
+        let first = states.len();
+        let id = |y: usize, x: usize| first + y * w + x;
+        (0..h).for_each(|y| (0..w).for_each(|x| {
+            let reach = [y, h - 1 - y, w - 1 - x, x];
+            let projections = (0..4).map(|dir| IF!(0 < reach[dir],
+                SuperState::from(std::iter::once(match dir { 0 => id(y - 1, x), 1 => id(y + 1, x), 2 => id(y, x + 1), _ => id(y, x - 1) })),
+                SuperState::from(tile.sides[dir][IF!(dir < 2, x, y)].iter().copied()))).collect();
+            states.push(State {
+                id: id(y, x),
+                glyph: Glyph::new(tile.color.clone(), tile.rows[y][x].to_string()),
+                weight: tile.weight,
+                projections,
+                reach
+            });
+        }));
+        (first..states.len()).for_each(|a| (0..4).for_each(|dir| {
+            if 0 < states[a].reach[dir] { return }
+            let n = states.len();
+            states[a].projections[dir].clone().states().filter(|b| *b < n).for_each(|b| states[b].projections[Square.opposite(dir)].insert(a));
+        }));
+    });
+    states
+}
+
+// Validation ////////////////////////////////////////////////////////
+
+// A mistake in a tileset's rules, found by validate
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleError {
+    Misnumbered(usize, usize),         // State at this index has this id
+    Directions(usize, usize),          // State has this many projections, unlike state 0
+    OutOfRange(usize, usize, usize),   // State allows this id in this direction
+    Empty(usize, usize),               // State allows nothing in this direction
+    Asymmetric(usize, usize, usize),   // State allows this one in this direction, not the reverse
+    Duplicate(usize, usize)            // State has the glyph and projections of this earlier one
+}
+
+impl Display for RuleError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        const DIRS: [&str; 4] = ["up", "down", "right", "left"];
+        let dir = |d: usize| DIRS.get(d).map_or(format!("in direction {}", d), |s| s.to_string());
+        match self {
+            RuleError::Misnumbered(i, id) => write!(fmt, "state {} has id {}", i, id),
+            RuleError::Directions(s, n) => write!(fmt, "state {} has {} projections, unlike state 0", s, n),
+            RuleError::OutOfRange(s, d, id) => write!(fmt, "state {} allows unknown state {} {}", s, id, dir(*d)),
+            RuleError::Empty(s, d) => write!(fmt, "state {} allows nothing {}", s, dir(*d)),
+            RuleError::Asymmetric(a, d, b) => write!(fmt, "state {} allows {} {} but {} doesn't allow {} back", a, b, dir(*d), b, a),
+            RuleError::Duplicate(s, of) => write!(fmt, "state {} duplicates state {}", s, of)
+        }
+    }
+}
+
+impl Error for RuleError {}
+
+// Everything wrong with a tileset's rules, empty when it's sound.  The
+// solver only keeps a neighbor pair both sides allow, so an asymmetric
+// pair is usually a typo.  Run it over a tileset before WaveFunction::new,
+// with the topology it's for, whose opposite pairs up the directions.
+pub fn validate (states: &[State], topology: &dyn Topology) -> Vec<RuleError> {
+    let n = states.len();
+    let dirs = states.first().map_or(0, |s| s.projections.len());
+    let mut errors = Vec::new();
+    states.iter().enumerate().for_each(|(i, s)| {
+        if s.id != i { errors.push(RuleError::Misnumbered(i, s.id)); }
+        if dirs != s.projections.len() { errors.push(RuleError::Directions(i, s.projections.len())); }
+    });
+    states.iter().enumerate().for_each(|(a, s)| s.projections.iter().take(dirs).enumerate().for_each(|(dir, p)| {
+        if 0 == p.count() { errors.push(RuleError::Empty(a, dir)); }
+        p.states().for_each(|b| {
+            if n <= b {
+                errors.push(RuleError::OutOfRange(a, dir, b));
+            } else if !states[b].projections.get(topology.opposite(dir)).is_some_and(|q| q.has(a)) {
+                errors.push(RuleError::Asymmetric(a, dir, b));
+            }
+        });
+    }));
+    states.iter().enumerate().for_each(|(i, s)| {
+        let same = |t: &State| t.glyph.glyph() == s.glyph.glyph()
+            && t.projections.len() == s.projections.len()
+            && t.projections.iter().zip(&s.projections).all(|(p, q)| 0 == p.minus(q).count() && 0 == q.minus(p).count());
+        if let Some(of) = states[..i].iter().position(same) { errors.push(RuleError::Duplicate(i, of)); }
+    });
+    errors
+}
+
+// How symmetrize settles a pair only one side allows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symmetrize {
+    Strict, // Drop it, as the solver would anyway
+    Loose   // Allow it on the other side too
+}
+
+// Make every allowed pair allowed from both sides, directions paired as
+// in topology.  Returns how many one-sided pairs were settled.  Out of
+// range ids are left to validate.
+pub fn symmetrize (states: &mut [State], topology: &dyn Topology, how: Symmetrize) -> usize {
+    let n = states.len();
+    let mut settled = 0;
+    (0..n).for_each(|a| (0..states[a].projections.len()).for_each(|dir| {
+        let allowed: Vec<usize> = states[a].projections[dir].states().filter(|b| *b < n).collect();
+        let back = topology.opposite(dir);
+        allowed.into_iter().for_each(|b| {
+            if states[b].projections.len() <= back || states[b].projections[back].has(a) { return }
+            settled += 1;
+            match how {
+                Symmetrize::Strict => states[a].projections[dir].remove(b),
+                Symmetrize::Loose => states[b].projections[back].insert(a)
+            }
+        });
+    }));
+    settled
+}
+
+// Example models ////////////////////////////////////////////////////
+
+// A sample's characters as rows, short lines padded with spaces
+fn sampleGrid (sample: &str) -> Vec<Vec<char>> {
+    let lines: Vec<Vec<char>> = sample.lines().map(|l| l.chars().collect()).collect();
+    let w = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+    lines.into_iter().map(|mut l| { l.resize(w, ' '); l }).collect()
+}
+
+// Learn a tileset from a sample drawn in characters.  Every NxN block of
+// the sample (wrapping around its edges when periodic) is a pattern, and
+// with symmetry 2..=8 so are that many of its mirror images and turns.
+// A pattern's weight is how often it was seen, and two patterns may be
+// neighbors wherever they agree on the cells they share.  A cell shows
+// its pattern's top left character.
+pub fn overlapping (sample: &str, n: usize, symmetry: usize, periodic: bool) -> Vec<State> {
+    assert!(0 < n, "pattern size must be at least 1");
+    assert!((1..=8).contains(&symmetry), "symmetry is 1 to 8 variants");
+    let grid = sampleGrid(sample);
+    let (h, w) = (grid.len(), grid.first().map_or(0, |r| r.len()));
+    let (hp, wp) = IF!(periodic, (h, w), ((h + 1).saturating_sub(n), (w + 1).saturating_sub(n)));
+    assert!(0 < hp && 0 < wp, "sample smaller than a pattern");
+    let block = |y: usize, x: usize| -> Vec<char> {
+        (0..n*n).map(|i| grid[(y + i / n) % h][(x + i % n) % w]).collect()
+    };
+    let turn = |p: &Vec<char>| -> Vec<char> { (0..n*n).map(|i| p[(n - 1 - i % n) * n + i / n]).collect() };
+    let mirror = |p: &Vec<char>| -> Vec<char> { (0..n*n).map(|i| p[i / n * n + n - 1 - i % n]).collect() };
+    // Patterns in the order first seen, so the same sample gives the same ids
+    let mut patterns: Vec<Vec<char>> = Vec::new();
+    let mut weights: Vec<f64> = Vec::new();
+    let mut index: HashMap<Vec<char>, usize> = HashMap::new();
+    (0..hp).for_each(|y| (0..wp).for_each(|x| {
+        let mut p = block(y, x);
+        (0..symmetry).for_each(|k| {
+            let q = IF!(1 == k % 2, mirror(&p), p.clone());
+            let i = *index.entry(q.clone()).or_insert_with(|| { patterns.push(q); weights.push(0.0); patterns.len() - 1 });
+            weights[i] += 1.0;
+            if 1 == k % 2 { p = turn(&p); }
+        });
+    }));
+    // q may sit in direction dir of p when they agree on their overlap
+    let agrees = |p: &Vec<char>, q: &Vec<char>, dir: usize| -> bool {
+        let (dy, dx): (isize, isize) = [(-1, 0), (1, 0), (0, 1), (0, -1)][dir];
+        let n = n as isize;
+        (0..n).all(|y| (0..n).all(|x| {
+            let (qy, qx) = (y - dy, x - dx);
+            !(0..n).contains(&qy) || !(0..n).contains(&qx) || p[(y * n + x) as usize] == q[(qy * n + qx) as usize]
+        }))
+    };
+    patterns.iter().zip(weights).enumerate().map(|(id, (p, weight))| State {
+        id,
+        glyph: Glyph::new(RST.to_string(), p[0].to_string()),
+        weight,
+        projections: (0..4).map(|dir| SuperState::from((0..patterns.len()).filter(|q| agrees(p, &patterns[*q], dir)))).collect(),
+        reach: [0; 4]
+    }).collect()
+}
+
+// Learn a tileset from an example map:  each character is a state,
+// weighted by how often it appears and allowing in each direction the
+// characters seen there.  Edges wrap when periodic.
+pub fn inferred (example: &str, periodic: bool) -> Vec<State> {
+    let grid = sampleGrid(example);
+    let (h, w) = (grid.len(), grid.first().map_or(0, |r| r.len()));
+    // Characters in the order first seen, so the same example gives the same ids
+    let mut chars: Vec<char> = Vec::new();
+    let mut index: HashMap<char, usize> = HashMap::new();
+    grid.iter().flatten().for_each(|c| { index.entry(*c).or_insert_with(|| { chars.push(*c); chars.len() - 1 }); });
+    let mut weights = vec![0.0; chars.len()];
+    let mut projections = vec![vec![SuperState::default(); 4]; chars.len()];
+    (0..h).for_each(|y| (0..w).for_each(|x| {
+        let id = index[&grid[y][x]];
+        weights[id] += 1.0;
+        [(y+h-1, x), (y+1, x), (y, x+1), (y, x+w-1)].iter().enumerate().for_each(|(dir, (ny, nx))| {
+            let inside = [0 < y, y+1 < h, x+1 < w, 0 < x][dir];
+            if inside || periodic { projections[id][dir].insert(index[&grid[ny % h][nx % w]]); }
+        });
+    }));
+    chars.iter().zip(weights).zip(projections).enumerate().map(|(id, ((c, weight), projections))| State {
+        id,
+        glyph: Glyph::new(RST.to_string(), c.to_string()),
+        weight,
+        projections,
+        reach: [0; 4]
+    }).collect()
+}
+
+// A tileset as the source of a function returning it, in the style of
+// the ones below, to paste in and edit
+pub fn tilesetSource (name: &str, states: &[State]) -> String {
+    let lines: Vec<String> = states.iter().map(|s| {
+        let projections: Vec<String> = s.projections.iter()
+            .map(|p| format!("&[{}]", p.states().map(|i| i.to_string()).collect::<Vec<String>>().join(",")))
+            .collect();
+        format!("        State::new({}, ({:?},{:?}), &[{}]){},", s.id, s.glyph.color, s.glyph.glyph, projections.join(","),
+            IF!(1.0 == s.weight, String::new(), format!(".weighted({:?})", s.weight)))
+    }).collect();
+    format!("pub fn {}States () -> Vec<State> {{\n    vec!(\n{}\n    )\n}}\n", name, lines.join("\n"))
+}
+
 ////////////////////////////////////////
 
+// Set of state ids, one bit each.  The first 64 states live in lo so the
+// usual tilesets never allocate; hi grows a word at a time past that.
+#[derive(Clone, Default)]
 struct SuperState {
-    states: HashSet<usize>
+    lo: u64,
+    hi: Vec<u64>
 }
 
 impl SuperState {
     fn from(states: impl Iterator<Item=usize>) -> SuperState {
-        SuperState{states: states.collect()}
+        let mut ss = SuperState::default();
+        states.for_each(|i| ss.insert(i));
+        ss
     }
-    fn intersect(&self, hss: &HashSet<usize>) -> HashSet<usize> {
-        &self.states & hss
+    fn word(&self, w: usize) -> u64 {
+        if 0 == w { self.lo } else { self.hi.get(w-1).copied().unwrap_or(0) }
+    }
+    fn insert(&mut self, i: usize) {
+        match i / 64 {
+            0 => self.lo |= 1 << i,
+            w => {
+                if self.hi.len() < w { self.hi.resize(w, 0); }
+                self.hi[w-1] |= 1 << (i % 64);
+            }
+        }
+    }
+    fn remove(&mut self, i: usize) {
+        match i / 64 {
+            0 => self.lo &= !(1 << i),
+            w => if let Some(word) = self.hi.get_mut(w-1) { *word &= !(1 << (i % 64)) }
+        }
+    }
+    fn has(&self, i: usize) -> bool {
+        0 != self.word(i / 64) & (1 << (i % 64))
+    }
+    fn intersect(&self, other: &SuperState) -> SuperState {
+        SuperState{
+            lo: self.lo & other.lo,
+            hi: self.hi.iter().zip(&other.hi).map(|(a, b)| a & b).collect()
+        }
+    }
+    fn union(&mut self, other: &SuperState) {
+        self.lo |= other.lo;
+        if self.hi.len() < other.hi.len() { self.hi.resize(other.hi.len(), 0); }
+        self.hi.iter_mut().zip(&other.hi).for_each(|(a, b)| *a |= b);
+    }
+    // States in self but not in other
+    fn minus(&self, other: &SuperState) -> SuperState {
+        SuperState{
+            lo: self.lo & !other.lo,
+            hi: self.hi.iter().enumerate().map(|(w, a)| a & !other.word(w+1)).collect()
+        }
     }
     fn count(&self) -> usize {
-        self.states.len()
+        (self.lo.count_ones() + self.hi.iter().map(|w| w.count_ones()).sum::<u32>()) as usize
     }
+    // State ids in ascending order
     fn states(&self) -> impl Iterator<Item=usize> + '_{
-        self.states.iter().map(|i|*i)
+        (0..=self.hi.len()).flat_map(move |w| {
+            let mut bits = self.word(w);
+            std::iter::from_fn(move || {
+                if 0 == bits { return None }
+                let b = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some(w*64 + b)
+            })
+        })
     }
     fn state(&self) -> usize {
-        *(self.states.iter().next().expect("superstate is empty"))
+        self.states().next().expect("superstate is empty")
     }
-    fn collapse(&mut self) {
-        let i = *self.states.iter().next().expect("superstate empty");
-        self.states.clear();
-        self.states.insert(i);
+    // Collapse to one state chosen proportionally to its weight.  All zero
+    // weights fall back to a uniform choice.
+    fn collapse(&mut self, rng: &mut Rng, weight: impl Fn(usize) -> f64) {
+        let states = self.states().collect::<Vec<usize>>();
+        let total: f64 = states.iter().map(|i| weight(*i)).sum();
+        let i = if 0.0 < total {
+            let mut r = rng.unit() * total;
+            let weighted: Vec<usize> = states.into_iter().filter(|i| 0.0 < weight(*i)).collect();
+            *weighted.iter()
+                .find(|i| { r -= weight(**i); r < 0.0 })
+                .unwrap_or(weighted.last().expect("no weight")) // Rounding may leave r a hair short
+        } else {
+            states[rng.below(states.len())]
+        };
+        *self = SuperState::from(std::iter::once(i));
     }
 }
 
 impl Debug for SuperState {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        fmt.write_str(&format!("{:?}", self.states))
+        fmt.debug_set().entries(self.states()).finish()
     }
 }
+
+////////////////////////////////////////
+
+// How the next cell to collapse is chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heuristic {
+    Count,  // Fewest remaining states, ties broken uniformly
+    Entropy // Lowest weighted Shannon entropy, ties broken by noise
+}
+
+// Running sums of a cell's remaining weights so its Shannon entropy
+//   H = ln(sum w) - sum(w ln w) / sum w
+// can be updated as states are removed instead of recomputed.
+#[derive(Clone)]
+struct Entropy {
+    sumw: f64,
+    sumwlogw: f64
+}
+
+impl Entropy {
+    fn from(weights: impl Iterator<Item=f64>) -> Entropy {
+        let mut e = Entropy{sumw: 0.0, sumwlogw: 0.0};
+        weights.for_each(|w| { e.sumw += w; e.sumwlogw += wlogw(w); });
+        e
+    }
+    fn remove(&mut self, w: f64) {
+        self.sumw -= w;
+        self.sumwlogw -= wlogw(w);
+    }
+    fn value(&self) -> f64 {
+        if self.sumw <= 0.0 { 0.0 } else { self.sumw.ln() - self.sumwlogw / self.sumw }
+    }
+}
+
+// Cell could still be, or can only be, one of the passable states
+fn mayPass (ss: &SuperState, passable: &SuperState) -> bool { 0 < ss.intersect(passable).count() }
+fn mustPass (ss: &SuperState, passable: &SuperState) -> bool { 0 < ss.count() && 0 == ss.minus(passable).count() }
+
+fn wlogw (w: f64) -> f64 { if 0.0 < w { w * w.ln() } else { 0.0 } }
+
+// Entry in the entropy min-heap.  Cells are pushed again whenever they
+// narrow, so an entry whose entropy no longer matches its cell is stale.
+struct Candidate {
+    key: f64,     // entropy plus noise
+    entropy: f64, // entropy when pushed
+    p: Point
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Candidate {
+    // Reversed so BinaryHeap pops the lowest entropy first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.total_cmp(&self.key).then_with(|| other.p.cmp(&self.p))
+    }
+}
+
+// How a change in one cell is carried to the others
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Propagator {
+    Projection, // Re-project each changed cell's states onto its neighbors
+    Ac4         // Per cell/direction/state support counts (AC-4)
+}
+
+// What lies past an edge of the grid, set per axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    Wrap,        // The opposite edge (torus)
+    Open,        // Nothing, so no constraint
+    Fixed(usize) // This state, which edge cells must allow next to them
+}
+
+// How cells connect:  how many directions each has, which way each
+// leads and which leads back.  A State has one projection per direction.
+pub trait Topology {
+    fn directions (&self) -> usize;
+    // (rows, columns, layers) moved going in direction dir from p
+    fn step (&self, p: &Point, dir: usize) -> (isize, isize, isize);
+    // The direction back, by default the other of the pair dir ^ 1
+    fn opposite (&self, dir: usize) -> usize { dir ^ 1 }
+    // Whether h rows can wrap top to bottom, each neighbor link then
+    // leading back the opposite way
+    fn wraps (&self, _h: usize) -> bool { true }
+    // Blank columns before row y on screen
+    fn indent (&self, _y: usize) -> usize { 0 }
+}
+
+// The default:  4 neighbors, 0 up, 1 down, 2 right, 3 left
+pub struct Square;
+
+impl Topology for Square {
+    fn directions (&self) -> usize { 4 }
+    fn step (&self, _p: &Point, dir: usize) -> (isize, isize, isize) {
+        [(-1, 0, 0), (1, 0, 0), (0, 1, 0), (0, -1, 0)][dir]
+    }
+}
+
+// Square plus the diagonal neighbors, 4 up right, 5 down left, 6 up left,
+// 7 down right.  States add the diagonal rules with State::diagonals.
+pub struct Moore;
+
+impl Topology for Moore {
+    fn directions (&self) -> usize { 8 }
+    fn step (&self, _p: &Point, dir: usize) -> (isize, isize, isize) {
+        [(-1, 0, 0), (1, 0, 0), (0, 1, 0), (0, -1, 0), (-1, 1, 0), (1, -1, 0), (-1, -1, 0), (1, 1, 0)][dir]
+    }
+}
+
+// Six neighbors in rows of hexes, odd rows sitting half a hex to the
+// right ("odd-r" offset coordinates).  0 up right, 1 down left, 2 right,
+// 3 left, 4 up left, 5 down right.  Glyphs should be two columns wide so
+// the half hex is one column.  Wrapping top to bottom needs an even
+// number of rows.
+pub struct Hex;
+
+impl Topology for Hex {
+    fn directions (&self) -> usize { 6 }
+    fn step (&self, p: &Point, dir: usize) -> (isize, isize, isize) {
+        let odd = (p.y & 1) as isize;
+        [(-1, odd, 0), (1, odd - 1, 0), (0, 1, 0), (0, -1, 0), (-1, odd - 1, 0), (1, odd, 0)][dir]
+    }
+    fn wraps (&self, h: usize) -> bool { 0 == h & 1 }
+    fn indent (&self, y: usize) -> usize { y & 1 }
+}
+
+// Voxels:  Square's directions in each layer, then 4 the layer above and
+// 5 the layer below
+pub struct Cube;
+
+impl Topology for Cube {
+    fn directions (&self) -> usize { 6 }
+    fn step (&self, _p: &Point, dir: usize) -> (isize, isize, isize) {
+        [(-1, 0, 0), (1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)][dir]
+    }
+}
+
+// A state needs a projection per direction.  Those it lacks allow any
+// state, so a Square tileset runs on Moore with unconstrained corners.
+fn fitProjections (basestates: &mut [State], dirs: usize) {
+    let any = SuperState::from(0..basestates.len());
+    basestates.iter_mut().for_each(|s| {
+        assert!(s.projections.len() <= dirs, "states need at most {} projections", dirs);
+        s.projections.resize(dirs, any.clone());
+    });
+}
+
+// Index i moved by d along an axis of n cells, None past a closed edge
+fn along (i: usize, d: isize, n: usize, boundary: Boundary) -> Option<usize> {
+    let j = i as isize + d;
+    if (0..n as isize).contains(&j) { return Some(j as usize) }
+    IF!(Boundary::Wrap == boundary, Some(j.rem_euclid(n as isize) as usize), None)
+}
+
+////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WfcError {
+    Contradiction(Point), // Cell left with no possible state
+    BacktrackLimit(usize), // Gave up after this many backtracks
+    AttemptLimit(usize),   // Gave up after this many restarts
+    CountLimit(usize),     // This state can't stay within its setCount limits
+    Disconnected(Point)    // Passable cell cut off from the others
+}
+
+impl Display for WfcError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            WfcError::Contradiction(p) => write!(fmt, "contradiction at {:?}", p),
+            WfcError::BacktrackLimit(n) => write!(fmt, "gave up after {} backtracks", n),
+            WfcError::AttemptLimit(n) => write!(fmt, "gave up after {} attempts", n),
+            WfcError::CountLimit(s) => write!(fmt, "state {} outside its count limits", s),
+            WfcError::Disconnected(p) => write!(fmt, "passable cell {:?} cut off", p)
+        }
+    }
+}
+
+impl Error for WfcError {}
+
+// A cell's state before a change, for undoing it
+struct Undo {
+    p: Point,
+    states: SuperState,
+    entropy: Entropy
+}
+
+// A collapse that may be undone and banned.  trail is the trail length
+// before the collapse.
+struct Decision {
+    p: Point,
+    state: usize,
+    trail: usize
+}
+
+struct Backtrack {
+    budget: usize,
+    used: usize,
+    trail: Vec<Undo>,
+    decisions: Vec<Decision>
+}
+
 ////////////////////////////////////////
 
 pub struct WaveFunction {
     term: Term,
-    top: usize,
+    top: usize, // Row of grid shown first, moved by scrolling
+    left: usize, // Column of grid shown first, moved by scrolling
+    view: (isize, isize), // Where the top left cell shown lies on the endless map
     cursor: (Point, Point),
     basestates: Vec<State>, // fixed vector of basis states
     lastColor: String,
-    grid: Vec<Vec<SuperState>>, // Grid of states (state == one or more possible values)
+    grid: Vec<Vec<SuperState>>, // Grid of states (state == one or more possible values), layers stacked
+    depth: usize, // Layers, each term.h rows
+    layer: usize, // The layer shown
+    boundary: (Boundary, Boundary, Boundary), // Past the top/bottom edges, the left/right edges, the lowest/highest layers
+    topology: Box<dyn Topology>,
     rowcount: Vec<usize>,
-    groups: Vec<HashSet<Point>> // Group values by wave count
+    groups: Vec<PointSet>, // Group values by wave count
+    heuristic: Heuristic,
+    entropy: Vec<Vec<Entropy>>, // Per cell entropy sums, kept current as cells narrow
+    candidates: BinaryHeap<Candidate>, // Only filled for Heuristic::Entropy
+    changed: Vec<(Point, SuperState)>, // Cells narrowed by the running propagation, prior states
+    propagator: Propagator,
+    supports: Vec<i32>, // Propagator::Ac4 counts, see supportIndex
+    removals: Vec<(Point, usize)>, // Propagator::Ac4 states removed but not yet propagated
+    backtrack: Option<Backtrack>, // None: contradictions are left as "!"
+    pins: Vec<(Point, SuperState)>, // From set and ban, applied again by reset
+    pinning: bool, // A pin is being applied, so contradictions are undone
+    limits: Vec<(usize, usize)>, // Per state min/max cell count, empty when unlimited
+    decided: Vec<usize>, // Per state cells collapsed to it, kept only with limits
+    possible: Vec<usize>, // Per state cells still allowing it, kept only with limits
+    passable: Option<SuperState>, // States that must form one connected region
+    reconnect: bool, // A cell's passability changed since connectivity was checked
+    frontier: Vec<Point>, // Open cells next to the connected region, collapsed first
+    failure: Option<WfcError>,
+    seed: u64,
+    rng: Rng
 }
 
 impl WaveFunction {
-    fn new (basestates: Vec<State>) -> WaveFunction {
-        let term = Term::new();
-        let numStates = basestates.len();
-        let mut groups: Vec<HashSet<_>> = (0..=numStates)
-            .into_iter()
-            .map(|_| HashSet::new())
-            .collect();
-        WaveFunction{
+    pub fn new (basestates: Vec<State>) -> WaveFunction {
+        WaveFunction::with_seed(basestates, clockseed())
+    }
+    // Same seed, same tileset and same terminal size => same map
+    pub fn with_seed (basestates: Vec<State>, seed: u64) -> WaveFunction {
+        WaveFunction::with_topology(basestates, Square, seed)
+    }
+    // For tilesets made for another topology.  The terminal's height loses
+    // a row if the topology can't wrap that many, see reset.
+    pub fn with_topology (mut basestates: Vec<State>, topology: impl Topology + 'static, seed: u64) -> WaveFunction {
+        fitProjections(&mut basestates, topology.directions());
+        let mut wf = WaveFunction{
+            term: Term::new(),
             top: 0,
+            left: 0,
+            view: (0, 0),
             cursor: (Point::new(0, 0), Point::new(0, 0)),
             basestates,
             lastColor: String::new(),
-            grid: (0..term.h).into_iter()
-                .map(|y| (0..term.w).into_iter()
-                    .map(|x| {
-                        groups[numStates].insert(Point::new(y, x));
-                        SuperState::from(0..numStates)
-                    }).collect())
-                .collect(),
-            rowcount: (0..term.h).map(|_|0).collect(),
-            groups,
-            term
-        }
+            grid: Vec::new(),
+            depth: 1,
+            layer: 0,
+            boundary: (Boundary::Wrap, Boundary::Wrap, Boundary::Open),
+            topology: Box::new(topology),
+            rowcount: Vec::new(),
+            groups: Vec::new(),
+            heuristic: Heuristic::Count,
+            entropy: Vec::new(),
+            candidates: BinaryHeap::new(),
+            changed: Vec::new(),
+            propagator: Propagator::Projection,
+            supports: Vec::new(),
+            removals: Vec::new(),
+            backtrack: None,
+            pins: Vec::new(),
+            pinning: false,
+            limits: Vec::new(),
+            decided: Vec::new(),
+            possible: Vec::new(),
+            passable: None,
+            reconnect: false,
+            frontier: Vec::new(),
+            failure: None,
+            seed,
+            rng: Rng::new(seed)
+        };
+        wf.reset(seed);
+        wf
     }
-    fn resetrow (&mut self) {
-        self.top = (self.top + self.term.h - 1) % self.term.h;
-        let row = self.top;
+    // Every cell back to a superposition of all states, as if just created
+    // with this seed.  Heuristic and backtracking settings are kept.  Rows
+    // that wrap top to bottom lose one, and any pins on it, if the topology
+    // can't wrap that many, whichever setting changed.
+    pub fn reset (&mut self, seed: u64) -> &mut Self {
+        if Boundary::Wrap == self.boundary.0 && !self.topology.wraps(self.term.h) {
+            self.term.h -= 1;
+            let h = self.term.h;
+            self.pins.retain(|(p, _)| p.y < h);
+        }
+        let (h, w) = (self.term.h * self.depth, self.term.w);
         let numStates = self.basestates.len();
-        (0..self.term.w).into_iter().for_each(|x| {
-            let p = Point::new(row, x);
-            self.groups[1].remove(&p);
-            self.groups[numStates].insert(p);
-            self.grid[row][x].states = (0..numStates).collect();
+        let entropy = Entropy::from(self.basestates.iter().map(|s| s.weight));
+        self.groups = (0..=numStates).map(|_| PointSet::new()).collect();
+        self.cells().for_each(|p| { self.groups[numStates].insert(p); });
+        self.grid = (0..h).map(|_| (0..w).map(|_| SuperState::from(0..numStates)).collect()).collect();
+        self.entropy = (0..h).map(|_| vec![entropy.clone(); w]).collect();
+        self.rowcount = vec![0; h];
+        self.top = 0;
+        self.left = 0;
+        self.view = (0, 0);
+        self.failure = None;
+        if let Some(bt) = self.backtrack.as_mut() {
+            bt.used = 0;
+            bt.trail.clear();
+            bt.decisions.clear();
+        }
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+        self.changed.clear();
+        self.removals.clear();
+        self.recount();
+        self.reconnect = true;
+        self.frontier.clear();
+        let propagator = self.propagator;
+        self.propagator = Propagator::Projection;
+        self.projectAll();
+        let pins = std::mem::take(&mut self.pins);
+        pins.iter().for_each(|(p, allowed)| if let Err(e) = self.applyPin(p, allowed) {
+            self.failure.get_or_insert(e);
         });
-        self.rowcount[row] = 0;
-        (0..self.term.w).into_iter().for_each(|x| {
-            let row = (row + 1) % self.term.h;
-            let p = Point::new(row, x);
-            self.projectdir(((p.y+self.term.h-1)%self.term.h, x), &p, 0);
+        self.pins = pins;
+        if self.enforce().is_none() {
+            let e = self.countViolation().map_or(WfcError::Contradiction(self.cursor.0.clone()), WfcError::CountLimit);
+            self.failure.get_or_insert(e);
+        }
+        self.setPropagator(propagator);
+        let heuristic = self.heuristic;
+        self.setHeuristic(heuristic);
+        self
+    }
+    // Remove states that a neighbor or a fixed boundary could never allow,
+    // and big tiles that wouldn't fit, so every propagator starts from the
+    // same arc consistent grid
+    fn projectAll (&mut self) {
+        self.cells().for_each(|p| (0..self.topology.directions()).for_each(|dir| {
+            if let Some(s) = self.fixedBeyond(&p, dir) {
+                let back = self.topology.opposite(dir);
+                self.projectFixed(&p, s, back);
+            }
+        }));
+        let closed = Boundary::Wrap != self.boundary.0 || Boundary::Wrap != self.boundary.1;
+        if closed && self.basestates.iter().any(|s| [0; 4] != s.reach) {
+            self.cells().for_each(|p| {
+                let ss = self.ss_ref(&p).minus(&self.overhanging(&p));
+                if ss.count() != self.ss_ref(&p).count() && self.narrowCell(&p, ss).is_none() {
+                    self.failure.get_or_insert(WfcError::Contradiction(p.clone()));
+                }
+            });
+        }
+        self.cells().for_each(|p| {
+            if self.projectState(&p).is_none() {
+                self.failure.get_or_insert(WfcError::Contradiction(p));
+            }
+        });
+        self.settle();
+    }
+    // Narrow p to what state s, lying past the edge, allows in direction dir
+    fn projectFixed (&mut self, p: &Point, s: usize, dir: usize) {
+        let ss = self.ss_ref(p).intersect(&self.basestates[s].projections[dir]);
+        if ss.count() != self.ss_ref(p).count() && self.narrowCell(p, ss).is_none() {
+            self.failure.get_or_insert(WfcError::Contradiction(p.clone()));
+        }
+    }
+    // Big tile cells whose tile would hang past an edge that doesn't wrap
+    fn overhanging (&self, p: &Point) -> SuperState {
+        SuperState::from(self.basestates.iter().filter(|s| (0..4).any(|dir| {
+            (0..s.reach[dir]).try_fold(p.clone(), |q, _| self.neighbor(&q, dir)).is_none()
+        })).map(|s| s.id))
+    }
+    pub fn seed (&self) -> u64 { self.seed }
+    // Resize, ignoring the terminal size arguments, and reset
+    // Pins outside the new size are dropped.  A row too many to wrap is
+    // dropped too.
+    pub fn setSize (&mut self, h: usize, w: usize) -> &mut Self {
+        self.term.h = h;
+        self.term.w = w;
+        self.pins.retain(|(p, _)| p.y < h && p.x < w);
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // Stack this many layers of the current size, for a volume, with what
+    // lies below the lowest and above the highest, and reset.  Pins in
+    // layers past the new depth are dropped.
+    pub fn setLayers (&mut self, depth: usize, z: Boundary) -> &mut Self {
+        assert!(0 < depth, "need a layer");
+        if let Boundary::Fixed(s) = z { assert!(s < self.basestates.len(), "boundary state {} out of range", s); }
+        self.depth = depth;
+        self.layer = self.layer.min(depth - 1);
+        self.boundary.2 = z;
+        self.pins.retain(|(p, _)| p.z < depth);
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // What lies past the top/bottom (y) and left/right (x) edges, and reset
+    pub fn setBoundary (&mut self, y: Boundary, x: Boundary) -> &mut Self {
+        [y, x].iter().for_each(|b| if let Boundary::Fixed(s) = b {
+            assert!(*s < self.basestates.len(), "boundary state {} out of range", s);
         });
+        self.boundary.0 = y;
+        self.boundary.1 = x;
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // How cells connect.  Resets.
+    pub fn setTopology (&mut self, topology: impl Topology + 'static) -> &mut Self {
+        fitProjections(&mut self.basestates, topology.directions());
+        self.topology = Box::new(topology);
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // Plotting off for solving without a terminal
+    pub fn setPlotting (&mut self, plot: bool) -> &mut Self {
+        self.term.plot = plot;
+        self
+    }
+    // Select the cell choosing heuristic.  Call before collapsing.
+    pub fn setHeuristic (&mut self, heuristic: Heuristic) -> &mut Self {
+        self.heuristic = heuristic;
+        self.candidates.clear();
+        self.cells().for_each(|p| self.pushCandidate(&p));
+        self
     }
+    // Select the propagator.  Either gives the same map for the same seed.
+    pub fn setPropagator (&mut self, propagator: Propagator) -> &mut Self {
+        self.propagator = propagator;
+        self.supports.clear();
+        if Propagator::Ac4 == propagator { self.buildSupports(); }
+        self
+    }
+    fn pushCandidate (&mut self, p: &Point) {
+        if Heuristic::Entropy != self.heuristic || !self.is_superpositioned(p) { return }
+        let entropy = self.entropy[self.row(p)][p.x].value();
+        let key = entropy + 1e-6 * self.rng.unit();
+        self.candidates.push(Candidate{key, entropy, p: p.clone()});
+    }
+    // Every cell, layer by layer
+    fn cells (&self) -> impl Iterator<Item = Point> + Clone {
+        let (h, w) = (self.term.h, self.term.w);
+        (0..self.depth).flat_map(move |z| (0..h).flat_map(move |y| (0..w).map(move |x| Point::voxel(y, x, z))))
+    }
+    // Row of grid holding p
+    fn row (&self, p: &Point) -> usize { p.z * self.term.h + p.y }
+    // p's position in cells()
+    fn cellIndex (&self, p: &Point) -> usize { self.row(p) * self.term.w + p.x }
     // Get superstate at point
-    fn ss (&mut self, p: &Point) -> &mut SuperState { &mut self.grid[p.y][p.x] }
-    fn ss_ref (&self, p: &Point) -> &    SuperState { &    self.grid[p.y][p.x] }
+    fn ss (&mut self, p: &Point) -> &mut SuperState { let y = self.row(p); &mut self.grid[y][p.x] }
+    fn ss_ref (&self, p: &Point) -> &    SuperState { &    self.grid[self.row(p)][p.x] }
     // Projection at location/direction:  Allowed states in that neighbor
-    fn projection_ss (&self, h: &HashSet<usize>, dir: usize) -> HashSet<usize> {
-        h.iter().map(|i|*i)
-            .flat_map(|id| self.basestates[id].projections[dir].states())
-            .collect::<HashSet<usize>>()
+    fn projection_ss (&self, h: &SuperState, dir: usize) -> SuperState {
+        let mut ss = SuperState::default();
+        h.states().for_each(|id| ss.union(&self.basestates[id].projections[dir]));
+        ss
     }
     fn is_superpositioned (&self, p: &Point) -> bool {
         2 <= self.ss_ref(p).count()
     }
-    fn projectdir(&mut self, (y,x): (usize, usize), op: &Point, dir: usize) -> Option<()> {
-        let p = Point::new(y, x);
+    // Narrow the cell at p to what op allows in direction dir.  Some(true)
+    // when it changed and its own neighbors need updating.
+    fn projectdir(&mut self, p: &Point, op: &Point, dir: usize) -> Option<bool> {
+        let p = p.clone();
         self.cursor = (p.clone(), op.clone());
         let sscount = self.ss(&p).count();
-        if sscount < 2 { return Some(()) } // Skip already collapsed state
-
-        // The "top row is ignored...disables y-axis torus mapping.
-        //if (op.y==self.top && 0==dir) || ((op.y+1)%self.term.h==self.top && 1==dir) { return }
+        if sscount < 1 { return Some(false) } // Skip emptied state, already a contradiction
 
-        let hashset2 = self.ss_ref(&p).intersect(&self.projection_ss(&self.ss_ref(&op).states, dir));
-        let sscountfinal = hashset2.len();
+        let ss2 = self.ss_ref(&p).intersect(&self.projection_ss(self.ss_ref(op), dir));
 
-        if sscount != sscountfinal {
-            self.ss(&p).states.clear();
-            self.ss(&p).states = hashset2;
-            if 1 == sscountfinal {
-                self.rowcount[p.y] += 1;
-            }
-            self.plotGlyph(&p);
-            self.groups[sscount].remove(&p);
-            self.groups[sscountfinal].insert(p.clone());
-            return match sscountfinal {
-                0 => Some(()),
-                _ => self.projectState(&p)
+        if sscount != ss2.count() {
+            return self.narrowCell(&p, ss2)
+        }
+        Some(false)
+    }
+    // Replace a cell's states with a subset and propagate the change.  None
+    // means a contradiction that the backtracker should unwind.
+    fn narrow(&mut self, p: &Point, states: SuperState) -> Option<()> {
+        match self.narrowCell(p, states) {
+            Some(true) => self.propagate(p),
+            Some(false) => { self.settle(); Some(()) }
+            None => {
+                self.propagateAc4(true);
+                self.rollback();
+                None
             }
         }
+    }
+    // Narrow without propagating.  Some(true) when neighbors need updating.
+    fn narrowCell(&mut self, p: &Point, states: SuperState) -> Option<bool> {
+        let sscount = self.ss_ref(p).count();
+        let sscountfinal = states.count();
+        self.record(p);
+        let old = std::mem::replace(self.ss(p), states);
+        self.tally(&old, &self.ss_ref(p).clone());
+        // An emptied cell only passes on its removals when they may be undone
+        if Propagator::Ac4 == self.propagator && (0 < sscountfinal || self.undoable()) {
+            let removed = old.minus(self.ss_ref(p));
+            removed.states().for_each(|id| self.removals.push((p.clone(), id)));
+        }
+        self.changed.push((p.clone(), old));
+        if 1 == sscountfinal && 2 <= sscount {
+            let y = self.row(p);
+            self.rowcount[y] += 1;
+        }
+        self.plotGlyph(p);
+        match sscountfinal {
+            0 => self.contradiction(p).map(|_| false),
+            _ => Some(true)
+        }
+    }
+    // Without backtracking a contradiction is remembered, plotted as "!" and
+    // solving carries on.  With it, or while pinning, propagation stops so it
+    // can be undone.
+    fn contradiction(&mut self, p: &Point) -> Option<()> {
+        if self.undoable() { return None }
+        self.failure.get_or_insert(WfcError::Contradiction(p.clone()));
         Some(())
     }
+    // Bring groups, entropy and candidates up to date with the cells the
+    // last propagation narrowed.  Done afterwards and in cell order so the
+    // next choice never depends on the order a propagator visited cells in.
+    fn settle(&mut self) {
+        let mut changed = std::mem::take(&mut self.changed);
+        changed.sort_by(|a, b| a.0.cmp(&b.0)); // Stable, so a cell's first change leads
+        changed.dedup_by(|a, b| a.0 == b.0);
+        changed.into_iter().for_each(|(p, old)| {
+            let y = self.row(&p);
+            let ss = &self.grid[y][p.x];
+            let basestates = &self.basestates;
+            let entropy = &mut self.entropy[y][p.x];
+            old.minus(ss).states().for_each(|id| entropy.remove(basestates[id].weight));
+            self.groups[old.count()].remove(&p);
+            self.groups[ss.count()].insert(p.clone());
+            self.pushCandidate(&p);
+        });
+    }
+    // Propagate a change at p with the selected propagator
+    fn propagate(&mut self, p: &Point) -> Option<()> {
+        let result = match self.propagator {
+            Propagator::Projection => self.projectState(p),
+            Propagator::Ac4 => self.propagateAc4(false)
+        };
+        match result {
+            Some(_) => self.settle(),
+            None => self.rollback()
+        }
+        result
+    }
+    // Propagate a change at p outward.  A stack of (cell, next direction)
+    // frames visits cells in the same depth first order recursion would,
+    // without running out of call stack on long chains over big grids.
     fn projectState(&mut self, p: &Point) -> Option<()> {
-        let y = p.y;
-        let x = p.x;
-        self.projectdir(((y+self.term.h-1)%self.term.h, x), p, 0)?;
-        self.projectdir(((y+1)            %self.term.h, x), p, 1)?;
-        self.projectdir((y, (x+1)            %self.term.w), p, 2)?;
-        self.projectdir((y, (x+self.term.w-1)%self.term.w), p, 3)
+        let mut stack = vec![(p.clone(), 0)];
+        let dirs = self.topology.directions();
+        while let Some((p, dir)) = stack.pop() {
+            if dir + 1 < dirs { stack.push((p.clone(), dir + 1)); }
+            let Some(q) = self.neighbor(&p, dir) else { continue };
+            if self.projectdir(&q, &p, dir)? {
+                stack.push((q, 0));
+            }
+        }
+        Some(())
+    }
+    // Neighbor of p in direction dir of the topology.  None past an edge
+    // that doesn't wrap.  Edges are those on screen, so the rows and columns
+    // meeting where a scrolled map starts aren't neighbors.
+    fn neighbor(&self, p: &Point, dir: usize) -> Option<Point> {
+        let (y, x) = (self.screenRow(p.y), self.screenColumn(p.x));
+        let (dy, dx, dz) = self.topology.step(&Point::voxel(self.stagger(y), x, p.z), dir);
+        Some(Point::voxel(
+            (along(y, dy, self.term.h, self.boundary.0)? + self.top) % self.term.h,
+            (along(x, dx, self.term.w, self.boundary.1)? + self.left) % self.term.w,
+            along(p.z, dz, self.depth, self.boundary.2)?))
+    }
+    // Screen row of grid row y, counting from the top one shown
+    fn screenRow(&self, y: usize) -> usize { (y + self.term.h - self.top) % self.term.h }
+    fn screenColumn(&self, x: usize) -> usize { (x + self.term.w - self.left) % self.term.w }
+    // Screen row y as the topology sees it, so hex rows keep their stagger
+    // as the view moves up and down
+    fn stagger(&self, y: usize) -> usize { y + self.view.0.rem_euclid(2) as usize }
+    // The cells of screen row y in layer z, left to right
+    fn shownRow(&self, z: usize, y: usize) -> impl Iterator<Item = &SuperState> {
+        let r = &self.grid[z * self.term.h + (y + self.top) % self.term.h];
+        (0..self.term.w).map(move |x| &r[(x + self.left) % r.len()])
+    }
+    // The fixed boundary state direction dir leads to from p, if any.  An
+    // open edge on the way wins over a fixed one.
+    fn fixedBeyond(&self, p: &Point, dir: usize) -> Option<usize> {
+        let (y, x) = (self.screenRow(p.y), self.screenColumn(p.x));
+        let (dy, dx, dz) = self.topology.step(&Point::voxel(self.stagger(y), x, p.z), dir);
+        let crossed = [
+            (along(y, dy, self.term.h, Boundary::Open).is_none(), self.boundary.0),
+            (along(x, dx, self.term.w, Boundary::Open).is_none(), self.boundary.1),
+            (along(p.z, dz, self.depth, Boundary::Open).is_none(), self.boundary.2)];
+        let mut fixed = None;
+        for (crossed, boundary) in crossed {
+            match (crossed, boundary) {
+                (true, Boundary::Open) => return None,
+                (true, Boundary::Fixed(s)) => { fixed.get_or_insert(s); }
+                _ => ()
+            }
+        }
+        fixed
     }
     fn collapseAt(&mut self, p: &Point) -> Option<()> {
         assert!(self.is_superpositioned(p)); // Should only collapse superstates
-        self.rowcount[p.y] += 1;
-        self.grid[p.y][p.x].collapse();
+        let trail = self.backtrack.as_ref().map(|bt| bt.trail.len());
+        self.record(p);
+        let y = self.row(p);
+        self.rowcount[y] += 1;
+        let old = self.ss_ref(p).clone();
+        let basestates = &self.basestates;
+        self.grid[y][p.x].collapse(&mut self.rng, |id| basestates[id].weight);
+        let state = self.stateAt(p);
+        self.tally(&old, &SuperState::from(std::iter::once(state)));
+        if Propagator::Ac4 == self.propagator {
+            old.states().filter(|id| *id != state).for_each(|id| self.removals.push((p.clone(), id)));
+        }
+        if let (Some(bt), Some(trail)) = (self.backtrack.as_mut(), trail) {
+            bt.decisions.push(Decision{p: p.clone(), state, trail});
+        }
         self.plotGlyph(p);
-        self.projectState(p)
+        self.propagate(p)
+    }
+    // AC-4 //////////////////////////////////////////////
+
+    // supports[supportIndex(p, dir, s)] counts the states left in p's
+    // neighbor in direction dir that allow state s at p
+    fn supportIndex(&self, p: &Point, dir: usize, state: usize) -> usize {
+        (self.cellIndex(p) * self.topology.directions() + dir) * self.basestates.len() + state
+    }
+    fn buildSupports(&mut self) {
+        let dirs = self.topology.directions();
+        let mut supports = vec![0; self.depth * self.term.h * self.term.w * dirs * self.basestates.len()];
+        self.cells().for_each(|p| {
+            (0..dirs).for_each(|dir| {
+                // Nothing past the edge to run out, so every state keeps a
+                // support there.  Fixed boundaries were applied by projectAll.
+                let Some(q) = self.neighbor(&p, dir) else {
+                    (0..self.basestates.len()).for_each(|s| supports[self.supportIndex(&p, dir, s)] = 1);
+                    return
+                };
+                self.ss_ref(&q).states().for_each(|t| {
+                    self.basestates[t].projections[self.topology.opposite(dir)].states()
+                        .for_each(|s| supports[self.supportIndex(&p, dir, s)] += 1);
+                });
+            });
+        });
+        self.supports = supports;
+    }
+    // States came back to p (undo or row reset) so count them again
+    fn supportAdd(&mut self, p: &Point, added: &SuperState) {
+        added.states().for_each(|t| (0..self.topology.directions()).for_each(|dir| {
+            let Some(q) = self.neighbor(p, dir) else { return };
+            let back = self.topology.opposite(dir);
+            self.basestates[t].projections[dir].clone().states().for_each(|s| {
+                let i = self.supportIndex(&q, back, s);
+                self.supports[i] += 1;
+            });
+        }));
+    }
+    // Each removed state lowers the counts of the states it allowed next
+    // door, and a state whose count reaches zero is removed in turn.  After a
+    // contradiction (failed) only the counts are kept right, for undoing.
+    fn propagateAc4(&mut self, mut failed: bool) -> Option<()> {
+        while let Some((p, t)) = self.removals.pop() {
+            (0..self.topology.directions()).for_each(|dir| {
+                let Some(q) = self.neighbor(&p, dir) else { return };
+                let back = self.topology.opposite(dir);
+                self.basestates[t].projections[dir].clone().states().for_each(|s| {
+                    let i = self.supportIndex(&q, back, s);
+                    self.supports[i] -= 1;
+                    if failed || 0 != self.supports[i] || !self.ss_ref(&q).has(s) { return }
+                    self.cursor = (q.clone(), p.clone());
+                    let mut states = self.ss_ref(&q).clone();
+                    states.remove(s);
+                    failed = self.narrowCell(&q, states).is_none();
+                });
+            });
+        }
+        IF!(failed, None, Some(()))
+    }
+    // Backtracking //////////////////////////////////////
+
+    // Enable backtracking, giving up after budget undone decisions
+    pub fn setBacktracking (&mut self, budget: usize) -> &mut Self {
+        self.backtrack = Some(Backtrack{budget, used: 0, trail: Vec::new(), decisions: Vec::new()});
+        self
+    }
+    pub fn backtracks (&self) -> usize {
+        self.backtrack.as_ref().map_or(0, |bt| bt.used)
+    }
+    fn undoable(&self) -> bool {
+        self.backtrack.is_some() || self.pinning
+    }
+    // Save a cell's state before it changes so it can be restored
+    fn record(&mut self, p: &Point) {
+        let y = self.row(p);
+        if let Some(bt) = self.backtrack.as_mut() {
+            bt.trail.push(Undo{
+                p: p.clone(),
+                states: self.grid[y][p.x].clone(),
+                entropy: self.entropy[y][p.x].clone()
+            });
+        }
+    }
+    // Put back a cell's earlier states.  Groups and candidates catch up in
+    // settle like after any other change.
+    fn restore(&mut self, p: &Point, states: SuperState) {
+        let count = self.ss_ref(p).count();
+        let countfinal = states.count();
+        if Propagator::Ac4 == self.propagator {
+            let added = states.minus(self.ss_ref(p));
+            self.supportAdd(p, &added);
+        }
+        if 1 == count && 2 <= countfinal { let y = self.row(p); self.rowcount[y] -= 1; }
+        let old = std::mem::replace(self.ss(p), states);
+        self.tally(&old, &self.ss_ref(p).clone());
+        self.changed.push((p.clone(), old));
+        self.plotGlyph(p);
+    }
+    // Undo a propagation that hit a contradiction.  It was never settled, so
+    // only the cells and their trail entries need putting back.
+    fn rollback(&mut self) {
+        let changed = std::mem::take(&mut self.changed);
+        if let Some(bt) = self.backtrack.as_mut() {
+            bt.trail.truncate(bt.trail.len() - changed.len());
+        }
+        changed.into_iter().rev().for_each(|(p, old)| self.restore(&p, old));
+        self.changed.clear();
+    }
+    // Undo the latest decision and ban its state in that cell, repeating
+    // while that leads to another contradiction.  False when out of budget
+    // or out of decisions to undo.
+    fn unwind(&mut self) -> bool {
+        loop {
+            let Some(bt) = self.backtrack.as_mut() else { return false };
+            if bt.budget <= bt.used {
+                self.failure = Some(WfcError::BacktrackLimit(bt.used));
+                return false
+            }
+            let Some(d) = bt.decisions.pop() else {
+                self.failure = Some(WfcError::Contradiction(self.cursor.0.clone()));
+                return false
+            };
+            bt.used += 1;
+            let undo = bt.trail.split_off(d.trail);
+            undo.into_iter().rev().for_each(|u| {
+                self.restore(&u.p, u.states);
+                let y = self.row(&u.p);
+                self.entropy[y][u.p.x] = u.entropy;
+            });
+            self.settle();
+            let mut states = self.ss_ref(&d.p).clone();
+            states.remove(d.state);
+            if self.narrow(&d.p, states).and_then(|_| self.enforce()).is_some() { return true }
+        }
+    }
+    // Pinning ///////////////////////////////////////////
+
+    // Force the cell at p to state before solving, say for an entrance.  Pins
+    // are kept through reset, so restarts honour them too.
+    pub fn set(&mut self, p: &Point, state: usize) -> Result<(), WfcError> {
+        assert!(state < self.basestates.len(), "state {} out of range", state);
+        self.pin(p, SuperState::from(std::iter::once(state)))
+    }
+    // Rule state out at p
+    pub fn ban(&mut self, p: &Point, state: usize) -> Result<(), WfcError> {
+        assert!(state < self.basestates.len(), "state {} out of range", state);
+        let mut allowed = SuperState::from(0..self.basestates.len());
+        allowed.remove(state);
+        self.pin(p, allowed)
+    }
+    // Forget all pins and reset
+    pub fn clearPins(&mut self) -> &mut Self {
+        self.pins.clear();
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // A pin that leads to a contradiction is undone and not kept
+    fn pin(&mut self, p: &Point, allowed: SuperState) -> Result<(), WfcError> {
+        assert!(p.y < self.term.h && p.x < self.term.w && p.z < self.depth, "pin {:?} outside the grid", p);
+        self.applyPin(p, &allowed)?;
+        self.pins.push((p.clone(), allowed));
+        Ok(())
+    }
+    fn applyPin(&mut self, p: &Point, allowed: &SuperState) -> Result<(), WfcError> {
+        let states = self.ss_ref(p).intersect(allowed);
+        if states.count() == self.ss_ref(p).count() { return Ok(()) }
+        self.pinning = true;
+        let result = self.narrow(p, states);
+        self.pinning = false;
+        result.ok_or(WfcError::Contradiction(p.clone()))
+    }
+    // Count limits //////////////////////////////////////
+
+    // Keep the number of cells collapsed to state within min..=max.  Checked
+    // while collapsing, so a limit that can't be met is a contradiction to
+    // backtrack from, or else a CountLimit failure.  Resets.
+    pub fn setCount(&mut self, state: usize, min: usize, max: usize) -> &mut Self {
+        assert!(state < self.basestates.len(), "state {} out of range", state);
+        if self.limits.is_empty() { self.limits = vec![(0, usize::MAX); self.basestates.len()]; }
+        self.limits[state] = (min, max);
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    fn recount(&mut self) {
+        if self.limits.is_empty() { return }
+        let numStates = self.basestates.len();
+        self.decided = vec![0; numStates];
+        self.possible = vec![0; numStates];
+        self.grid.iter().flatten().for_each(|ss| {
+            ss.states().for_each(|s| self.possible[s] += 1);
+            if 1 == ss.count() { self.decided[ss.state()] += 1; }
+        });
+    }
+    // A cell changed from old to new
+    fn tally(&mut self, old: &SuperState, new: &SuperState) {
+        if let Some(passable) = &self.passable {
+            if mayPass(old, passable) != mayPass(new, passable) || mustPass(old, passable) != mustPass(new, passable) {
+                self.reconnect = true;
+            }
+        }
+        if self.limits.is_empty() { return }
+        old.minus(new).states().for_each(|s| self.possible[s] -= 1);
+        new.minus(old).states().for_each(|s| self.possible[s] += 1);
+        if 1 == old.count() { self.decided[old.state()] -= 1; }
+        if 1 == new.count() { self.decided[new.state()] += 1; }
+    }
+    fn countViolation(&self) -> Option<usize> {
+        (0..self.limits.len()).find(|s| {
+            let (min, max) = self.limits[*s];
+            max < self.decided[*s] || self.possible[*s] < min
+        })
+    }
+    // Ban a state from every open cell once it reaches its max, and force it
+    // on every cell still allowing it once that's only just its min.
+    fn enforceCounts(&mut self) -> Option<()> {
+        loop {
+            if let Some(s) = self.countViolation() {
+                if self.undoable() { return None }
+                self.failure.get_or_insert(WfcError::CountLimit(s));
+                return Some(())
+            }
+            let Some((s, force)) = (0..self.limits.len()).find_map(|s| {
+                let (min, max) = self.limits[s];
+                let open = self.decided[s] < self.possible[s];
+                IF!(open && max == self.decided[s], Some((s, false)),
+                IF!(open && min == self.possible[s], Some((s, true)), None))
+            }) else { return Some(()) };
+            for p in self.cells() {
+                let ss = self.ss_ref(&p);
+                if ss.count() < 2 || !ss.has(s) { continue }
+                let states = IF!(force, SuperState::from(std::iter::once(s)), { let mut ss = ss.clone(); ss.remove(s); ss });
+                self.narrow(&p, states)?;
+            }
+        }
+    }
+    // Connectivity //////////////////////////////////////
+
+    // Require the cells that end up as any of these states to form one
+    // region, connected through neighbors.  Resets.
+    pub fn setConnected(&mut self, passable: &[usize]) -> &mut Self {
+        assert!(passable.iter().all(|s| *s < self.basestates.len()), "passable state out of range");
+        self.passable = Some(SuperState::from(passable.iter().copied()));
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // Count limits and connectivity, after every collapse
+    fn enforce(&mut self) -> Option<()> {
+        loop {
+            self.enforceCounts()?;
+            if !self.enforceConnected()? { return Some(()) }
+        }
+    }
+    // Depth first search (Tarjan) over the cells that may still be passable,
+    // from one that must be.  A must cell it doesn't reach is a
+    // contradiction.  A may cell it doesn't reach must be impassable, and one
+    // whose removal would cut off a must cell (articulation point) must be
+    // passable.  Some(true) when cells were narrowed.
+    fn enforceConnected(&mut self) -> Option<bool> {
+        if !self.reconnect { return Some(false) }
+        let Some(passable) = self.passable.clone() else { return Some(false) };
+        self.reconnect = false;
+        let (h, w) = (self.term.h, self.term.w);
+        let n = self.depth * h * w;
+        let cells = self.cells();
+        let Some(root) = cells.clone().find(|p| mustPass(self.ss_ref(p), &passable)) else { return Some(false) };
+        let index = |p: &Point| (p.z * h + p.y) * w + p.x;
+        let mut disc = vec![0; n]; // Discovery time, 0 unvisited
+        let mut low = vec![0; n];  // Earliest discovery reachable from the subtree
+        let mut must = vec![false; n]; // Subtree holds a must cell
+        let mut cut = vec![false; n];
+        let mut time = 1;
+        disc[index(&root)] = time;
+        low[index(&root)] = time;
+        must[index(&root)] = true;
+        let dirs = self.topology.directions();
+        let mut stack = vec![(root, 0)];
+        while let Some((p, dir)) = stack.pop() {
+            let i = index(&p);
+            if dir < dirs {
+                stack.push((p.clone(), dir + 1));
+                let Some(q) = self.neighbor(&p, dir) else { continue };
+                let j = index(&q);
+                if !mayPass(self.ss_ref(&q), &passable) { continue }
+                if 0 == disc[j] {
+                    time += 1;
+                    disc[j] = time;
+                    low[j] = time;
+                    must[j] = mustPass(self.ss_ref(&q), &passable);
+                    stack.push((q, 0));
+                } else {
+                    low[i] = low[i].min(disc[j]);
+                }
+            } else if let Some((parent, _)) = stack.last() {
+                let k = index(parent);
+                low[k] = low[k].min(low[i]);
+                if must[i] {
+                    must[k] = true;
+                    if disc[k] <= low[i] { cut[k] = true; }
+                }
+            }
+        }
+        self.frontier = cells.clone().filter(|p| 0 < disc[index(p)] && self.is_superpositioned(p) && (0..dirs)
+            .filter_map(|dir| self.neighbor(p, dir))
+            .any(|q| mustPass(self.ss_ref(&q), &passable))).collect();
+        let mut narrowed = false;
+        for p in cells {
+            let ss = self.ss_ref(&p);
+            if !mayPass(ss, &passable) { continue }
+            let i = index(&p);
+            let states = if 0 == disc[i] {
+                if mustPass(ss, &passable) {
+                    self.cursor.0 = p.clone();
+                    if self.undoable() { return None }
+                    self.failure.get_or_insert(WfcError::Disconnected(p));
+                    return Some(false)
+                }
+                ss.minus(&passable)
+            } else if cut[i] && !mustPass(ss, &passable) {
+                ss.intersect(&passable)
+            } else {
+                continue
+            };
+            self.narrow(&p, states)?;
+            narrowed = true;
+        }
+        Some(narrowed)
+    }
+    // Hierarchy /////////////////////////////////////////
+
+    // Detail for a solved coarse map, each coarse cell becoming scale by
+    // scale fine cells.  zones lists the fine states each coarse state
+    // allows.  A fine cell takes its own zone's states or those of any zone
+    // within blend cells of it, so zones meet through states both allow.
+    // Resizes, replaces the pins with the zones and resets.
+    pub fn refine (&mut self, coarse: &WaveFunction, scale: usize, zones: &[&[usize]], blend: usize) -> &mut Self {
+        assert!(0 < scale, "scale must be at least 1");
+        assert!(1 == self.depth && 1 == coarse.depth, "flat maps only");
+        assert!(zones.len() == coarse.basestates.len(), "one zone per coarse state");
+        assert!(zones.iter().flat_map(|z| z.iter()).all(|s| *s < self.basestates.len()), "zone state out of range");
+        assert!(coarse.cells().all(|p| 1 == coarse.ss_ref(&p).count()), "coarse map isn't solved");
+        let zones: Vec<SuperState> = zones.iter().map(|z| SuperState::from(z.iter().copied())).collect();
+        let (h, w) = (coarse.term.h * scale, coarse.term.w * scale);
+        let (by, bx) = (self.boundary.0, self.boundary.1);
+        let b = blend as isize;
+        self.pins = (0..h).flat_map(|y| (0..w).map(move |x| Point::new(y, x))).map(|p| {
+            let mut allowed = SuperState::default();
+            (-b..=b).for_each(|dy| (-b..=b).for_each(|dx| {
+                let (Some(y), Some(x)) = (along(p.y, dy, h, by), along(p.x, dx, w, bx)) else { return };
+                allowed.union(&zones[coarse.stateAt(&Point::new(y / scale, x / scale))]);
+            }));
+            (p, allowed)
+        }).collect();
+        self.term.h = h;
+        self.term.w = w;
+        let seed = self.seed;
+        self.reset(seed)
+    }
+    // Scrolling /////////////////////////////////////////
+
+    // Move the view of a solved map a cell, 0 up 1 down 2 right 3 left:
+    // the row or column on the far side goes and a new one comes in on
+    // this side, solved against its neighbors.  Only the cells on screen
+    // are kept, so going back finds new ground.  A contradiction in the new
+    // line is backtracked, with backtracking, and otherwise the line is
+    // cleared and tried again, up to attempts tries in all.  Ok holds the
+    // tries used.  The map's edges the way it moves open, no longer
+    // wrapping or held to a fixed state, since the ground goes on past
+    // them.  Connectivity isn't kept, as lines leaving can cut the map
+    // apart.
+    pub fn pan (&mut self, dir: usize, attempts: usize) -> Result<usize, WfcError> {
+        assert!(1 == self.depth, "flat maps only");
+        let (h, w) = (self.term.h, self.term.w);
+        let line = match dir {
+            0 => { self.top = (self.top + h - 1) % h; self.top }
+            1 => { self.top = (self.top + 1) % h; (self.top + h - 1) % h }
+            2 => { self.left = (self.left + 1) % w; (self.left + w - 1) % w }
+            3 => { self.left = (self.left + w - 1) % w; self.left }
+            _ => panic!("no direction {}", dir)
+        };
+        let (dy, dx) = [(-1, 0), (1, 0), (0, 1), (0, -1)][dir];
+        self.view = (self.view.0 + dy, self.view.1 + dx);
+        let row = dir < 2;
+        *IF!(row, &mut self.boundary.0, &mut self.boundary.1) = Boundary::Open;
+        let cells: Vec<Point> = IF!(row, (0..w).map(|x| Point::new(line, x)).collect(), (0..h).map(|y| Point::new(y, line)).collect());
+        let done = |wf: &Self| IF!(row, w == wf.rowcount[line], cells.iter().all(|p| 1 == wf.ss_ref(p).count()));
+        let plot = std::mem::replace(&mut self.term.plot, false); // Shown when done
+        let passable = self.passable.take();
+        let mut result = Err(WfcError::AttemptLimit(attempts));
+        for attempt in 1..=attempts {
+            self.clearLine(&cells, row);
+            while self.failure.is_none() && !done(self) && self.collapseMaybe() { }
+            if self.failure.take().is_none() && done(self) {
+                result = Ok(attempt);
+                break
+            }
+        }
+        self.term.plot = plot;
+        self.passable = passable;
+        self.reconnect = true;
+        result
+    }
+    // Scroll a solved map up a row, a new one coming in at the bottom.  The
+    // row is done once rowcount says all its cells are.
+    pub fn scrollRow (&mut self, attempts: usize) -> Result<usize, WfcError> {
+        self.pan(1, attempts)
+    }
+    // Scroll rows up the screen, speed rows a second, printing each new
+    // row once solved.  Stops at a row that can't be solved.
+    pub fn scroll (&mut self, rows: usize, speed: f64) -> Result<(), WfcError> {
+        assert!(0.0 < speed, "speed must be positive");
+        print!("\x1b[1;{}r", self.term.h); // Only the map's rows scroll
+        let result = (0..rows).try_for_each(|_| {
+            self.scrollRow(100)?;
+            print!("\x1b[{}H\x1b[S", self.term.h);
+            self.printRow(self.term.h - 1);
+            stdout().flush().ok();
+            sleep(1.0 / speed);
+            Ok(())
+        });
+        print!("\x1b[r{RST}");
+        self.lastColor.clear();
+        result
+    }
+    // Follow a camera path over the endless map, speed cells a second,
+    // redrawing the view each step.  The path lists where the top left
+    // cell goes in turn, counted from where it was at reset.  Each leg
+    // steps along whichever axis has further to go, so a slanted leg is a
+    // staircase.  Stops at a line that can't be solved.
+    pub fn travel (&mut self, path: &[(isize, isize)], speed: f64) -> Result<(), WfcError> {
+        assert!(0.0 < speed, "speed must be positive");
+        let result = path.iter().try_for_each(|to| {
+            while *to != self.view {
+                let (dy, dx) = (to.0 - self.view.0, to.1 - self.view.1);
+                self.pan(IF!(dx.abs() <= dy.abs(), IF!(dy < 0, 0, 1), IF!(dx < 0, 3, 2)), 100)?;
+                print!("{HOM}");
+                self.print();
+                print!("\x1b[{}H\x1b[1;37;41mseed {} at {},{}\x1b[0m", self.term.h, self.seed, self.view.0, self.view.1);
+                stdout().flush().ok();
+                sleep(1.0 / speed);
+            }
+            Ok(())
+        });
+        self.lastColor.clear();
+        result
+    }
+    // A grid row, or column, back to all states, then narrowed to what its
+    // neighbors and a fixed edge allow.  The rest are final, so the
+    // backtracking trail starts over.
+    fn clearLine (&mut self, cells: &[Point], row: bool) {
+        let numStates = self.basestates.len();
+        let entropy = Entropy::from(self.basestates.iter().map(|s| s.weight));
+        cells.iter().for_each(|p| {
+            let count = self.ss_ref(p).count();
+            self.groups[count].remove(p);
+            self.groups[1].remove(p);
+            self.groups[numStates].insert(p.clone());
+            let old = std::mem::replace(self.ss(p), SuperState::from(0..numStates));
+            self.tally(&old, &SuperState::from(0..numStates));
+            self.entropy[p.y][p.x] = entropy.clone();
+        });
+        let rows = IF!(row, cells[0].y..cells[0].y + 1, 0..self.term.h);
+        rows.for_each(|y| self.rowcount[y] = self.grid[y].iter().filter(|ss| 1 == ss.count()).count());
+        self.failure = None;
+        self.frontier.clear();
+        if let Some(bt) = self.backtrack.as_mut() {
+            bt.used = 0;
+            bt.trail.clear();
+            bt.decisions.clear();
+        }
+        let propagator = self.propagator;
+        self.propagator = Propagator::Projection;
+        cells.iter().for_each(|p| (0..self.topology.directions()).for_each(|dir| {
+            let back = self.topology.opposite(dir);
+            match self.neighbor(p, dir) {
+                Some(q) if IF!(row, q.y != p.y, q.x != p.x) => if self.projectdir(p, &q, back).is_none() {
+                    self.failure.get_or_insert(WfcError::Contradiction(p.clone()));
+                },
+                Some(_) => (),
+                None => if let Some(s) = self.fixedBeyond(p, dir) { self.projectFixed(p, s, back) }
+            }
+        }));
+        cells.iter().for_each(|p| if self.projectState(p).is_none() {
+            self.failure.get_or_insert(WfcError::Contradiction(p.clone()));
+        });
+        self.settle();
+        self.setPropagator(propagator); // Supports counted again across the lines that met or parted
+        cells.iter().for_each(|p| self.pushCandidate(p));
     }
+    // Solving ///////////////////////////////////////////
+
     fn getLowestEntropy(&mut self) -> Option<Point> {
+        if let Some(p) = self.getFrontier() { return Some(p) }
+        if Heuristic::Entropy == self.heuristic {
+            return self.getLowestShannon();
+        }
+        let rng = &mut self.rng;
         self.groups.iter_mut()
             .skip(2)
             .find(|h| 0<h.len())
-            .map(|h| h.take(&h.iter().next().expect("impossible").clone()).expect("not possible"))
-            .map(|p| { self.groups[1].insert(p.clone()); p })
+            .and_then(|h| h.pick(rng))
+            .inspect(|p| { self.groups[1].insert(p.clone()); })
+    }
+    // With connectivity, grow the region from its edge rather than start
+    // islands that must meet up later.  Fewest states first.
+    fn getFrontier(&mut self) -> Option<Point> {
+        let mut frontier = std::mem::take(&mut self.frontier);
+        frontier.retain(|p| self.is_superpositioned(p));
+        let fewest = frontier.iter().map(|p| self.ss_ref(p).count()).min()?;
+        let ties: Vec<&Point> = frontier.iter().filter(|p| self.ss_ref(p).count() == fewest).collect();
+        let p = ties[self.rng.below(ties.len())].clone();
+        self.groups[fewest].remove(&p);
+        self.groups[1].insert(p.clone());
+        self.frontier = frontier;
+        Some(p)
+    }
+    fn getLowestShannon(&mut self) -> Option<Point> {
+        loop {
+            let c = self.candidates.pop()?;
+            if self.is_superpositioned(&c.p) && c.entropy == self.entropy[self.row(&c.p)][c.p.x].value() {
+                let count = self.ss_ref(&c.p).count();
+                self.groups[count].remove(&c.p);
+                self.groups[1].insert(c.p.clone());
+                return Some(c.p)
+            }
+        }
     }
     pub fn collapseMaybe(&mut self) -> bool {
         match self.getLowestEntropy() {
-            Some(p) => { match self.collapseAt(&p) { Some(_) => true, None => false} }
+            Some(p) => { match self.collapseAt(&p).and_then(|_| self.enforce()) { Some(_) => true, None => self.unwind() } }
             None => false
         }
     }
+    // Collapse every cell.  Fails on the first contradiction, or with
+    // backtracking enabled once the budget is spent.
+    pub fn solve(&mut self) -> Result<(), WfcError> {
+        while self.collapseMaybe() { }
+        match self.failure.take() {
+            Some(e) => Err(e),
+            None => Ok(())
+        }
+    }
+    // Solve, starting over with the next seed as soon as any cell is left
+    // with no state.  Ok holds the number of attempts used.  A failure
+    // there from the start, say a count limit the grid can't meet, comes
+    // from the pins and limits and not the seed, so it's returned as is.
+    pub fn solveRestarting(&mut self, attempts: usize) -> Result<usize, WfcError> {
+        for attempt in 1..=attempts {
+            if let Some(e) = self.failure.take() { return Err(e) }
+            while self.failure.is_none() && self.collapseMaybe() { }
+            if self.failure.take().is_none() { return Ok(attempt) }
+            if attempt < attempts {
+                let seed = self.seed.wrapping_add(1);
+                self.reset(seed);
+            }
+        }
+        Err(WfcError::AttemptLimit(attempts))
+    }
     pub fn stateAt (&self, p: &Point) -> usize {
-        self.grid[p.y][p.x].state()
+        self.ss_ref(p).state()
     }
     pub fn glyphAt (&self, p: &Point) -> &Glyph {
         &self.basestates[self.stateAt(p)].glyph
     }
     pub fn plotGlyph(&mut self, p: &Point) {
+        if !self.term.plot || p.z != self.layer { return }
 
-        let (clr, gly) = match self.ss_ref(&p).states.len() {
+        let (clr, gly) = match self.ss_ref(p).count() {
             0 => ("\x1b[0;5m", "!"),
             1 => {
                 let glyph = &self.glyphAt(p);
@@ -303,28 +1910,77 @@ impl WaveFunction {
                 //print!("\x1b[{};{}H{}{}", p.y+1, p.x+1, self.lastColor, c);
             }
         };
-        if &self.lastColor != clr {
-            print!("\x1b[{};{}H{}{}", p.y+1, p.x+1, clr, gly);
+        let width = self.cellWidth();
+        let y = self.screenRow(p.y);
+        let column = self.topology.indent(self.stagger(y)) + self.screenColumn(p.x) * width;
+        if self.lastColor != clr {
+            print!("\x1b[{};{}H{}{:<width$}", y+1, column+1, clr, gly);
             self.lastColor = clr.to_string();
         } else {
-            print!("\x1b[{};{}H{}", p.y+1, p.x+1, gly);
+            print!("\x1b[{};{}H{:<width$}", y+1, column+1, gly);
         }
     }
+    // Columns a cell takes on screen, as wide as the first state's glyph
+    fn cellWidth (&self) -> usize {
+        self.basestates.first().map_or(1, |s| s.glyph.glyph.chars().count().max(1))
+    }
     pub fn print (&self) -> &Self { print!("{}\x1b[0m", self); self }
-    pub fn printTop (&self) -> &Self {
-        let y = self.top;
-        let r = &self.grid[y];
-        r.iter().for_each(|ss| {
-            match ss.states.len() {
-                0 => print!("     "),
-                1 => print!("{}", self.basestates[*ss.states.iter().next().expect("should not occur")].glyph.glyph()),
-                l => print!("{}", l)
-            };
+    // Seed and outcome on the bottom row so a good map can be regenerated
+    pub fn printResult (&self, result: &Result<usize, WfcError>) -> &Self {
+        match result {
+            Ok(attempts) => print!("\x1b[{}H\x1b[1;37;41mseed {} attempts {}\x1b[0m", self.term.h, self.seed, attempts),
+            Err(e) => print!("\x1b[{}H\x1b[1;37;41mseed {} {}\x1b[0m", self.term.h, self.seed, e)
+        }
+        self
+    }
+    // Print screen row y in place
+    pub fn printRow (&self, y: usize) -> &Self {
+        let width = self.cellWidth();
+        print!("\x1b[{}H{}", y+1, " ".repeat(self.topology.indent(self.stagger(y))));
+        self.shownRow(self.layer, y).for_each(|ss| match ss.count() {
+            1 => print!("{}", self.basestates[ss.state()].glyph.glyph()),
+            l => print!("\x1b[0m{:<width$}", l)
         });
-        print!("\n");
+        print!("\x1b[0m");
         self
     }
     pub fn debug (&self) -> &Self { print!("{:?}\x1b[0m", self); self }
+    // The map as plain characters, "*" where undecided and "!" where empty.
+    // Layers from the lowest up, a blank line between them.
+    pub fn text (&self) -> String {
+        let width = self.cellWidth();
+        (0..self.depth).map(|z| (0..self.term.h).map(|y| {
+            " ".repeat(self.topology.indent(self.stagger(y))) + &self.shownRow(z, y).map(|ss| match ss.count() {
+                0 => format!("{:<width$}", "!"),
+                1 => self.basestates[ss.state()].glyph.glyph.clone(),
+                _ => format!("{:<width$}", "*")
+            }).collect::<String>()
+        }).collect::<Vec<String>>().join("\n")).collect::<Vec<String>>().join("\n\n")
+    }
+    // Plain text voxel dump:  a "layers rows columns" line, then text
+    pub fn voxels (&self) -> String {
+        format!("{} {} {}\n{}\n", self.depth, self.term.h, self.term.w, self.text())
+    }
+    // Show layer z, the one plotted and printed
+    pub fn setLayer (&mut self, z: usize) -> &mut Self {
+        self.layer = z.min(self.depth - 1);
+        self
+    }
+    // Page through the layers:  u or k and Enter up a layer, d or j down,
+    // anything else quits
+    pub fn browse (&mut self) -> &mut Self {
+        loop {
+            print!("{HOM}{CLR}");
+            self.print();
+            print!("\x1b[{}H\x1b[1;37;44mlayer {} of {}  u/d then Enter, q quits\x1b[0m ", self.term.h, self.layer + 1, self.depth);
+            stdout().flush().ok();
+            match readline().trim() {
+                "u" | "k" => { self.setLayer(self.layer + 1); }
+                "d" | "j" => { self.setLayer(self.layer.saturating_sub(1)); }
+                _ => return self
+            }
+        }
+    }
 }
 
 impl Debug for WaveFunction {
@@ -334,19 +1990,19 @@ impl Debug for WaveFunction {
                 let p = Point::new(y,x);
                 let mut s = 0;
                 if self.cursor.1 == p {
-                    fmt.write_str(&"\x1b[44m" )
+                    fmt.write_str("\x1b[44m" )
                 } else if self.cursor.0 == p {
-                    fmt.write_str(&"\x1b[42m" )
+                    fmt.write_str("\x1b[42m" )
                 } else {
-                    fmt.write_str(&"\x1b[100m" )
+                    fmt.write_str("\x1b[100m" )
                 }.ok();
-                if 1 == ss.states.len() { fmt.write_str("\x1b[0;1m").ok(); }
-                fmt.write_str(if ss.states.get(&0).is_some() { &" " } else { s+=1; &"" } ).ok();
-                fmt.write_str(if ss.states.get(&1).is_some() { &"+" } else { s+=1; &"" } ).ok();
-                fmt.write_str(if ss.states.get(&2).is_some() { &"-" } else { s+=1; &"" } ).ok();
-                fmt.write_str(if ss.states.get(&3).is_some() { &"|" } else { s+=1; &"" } ).ok();
-                fmt.write_str(if ss.states.get(&4).is_some() { &"#" } else { s+=1; &"" } ).ok();
-                fmt.write_str(&"\x1b[0m " ).ok();
+                if 1 == ss.count() { fmt.write_str("\x1b[0;1m").ok(); }
+                fmt.write_str(if ss.has(0) { " " } else { s+=1; "" } ).ok();
+                fmt.write_str(if ss.has(1) { "+" } else { s+=1; "" } ).ok();
+                fmt.write_str(if ss.has(2) { "-" } else { s+=1; "" } ).ok();
+                fmt.write_str(if ss.has(3) { "|" } else { s+=1; "" } ).ok();
+                fmt.write_str(if ss.has(4) { "#" } else { s+=1; "" } ).ok();
+                fmt.write_str("\x1b[0m " ).ok();
                 fmt.write_str(&"     "[0..s] ).ok();
             });
             fmt.write_str("\n").ok();
@@ -358,14 +2014,14 @@ impl Debug for WaveFunction {
 
 impl Display for WaveFunction {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        (0..self.term.h).into_iter().for_each(|y| {
-            let y = (y + self.top) % self.term.h;
-            let r = &self.grid[y];
+        (0..self.term.h).for_each(|y| {
+            let width = self.cellWidth();
             //fmt.write_str(&format!("{:3} ", self.rowcount[y])).ok();
-            r.iter().for_each(|ss| {
-                match ss.states.len() {
-                    1 => fmt.write_str(&self.basestates[*ss.states.iter().next().expect("can not occur")].glyph.glyph()),
-                    l => fmt.write_str(&format!("\x1b[0m{}", l))
+            fmt.write_str(&" ".repeat(self.topology.indent(self.stagger(y)))).ok();
+            self.shownRow(self.layer, y).for_each(|ss| {
+                match ss.count() {
+                    1 => fmt.write_str(&self.basestates[ss.state()].glyph.glyph()),
+                    l => fmt.write_str(&format!("\x1b[0m{:<width$}", l))
                 }.ok();
             });
             if y < self.term.h-1 { fmt.write_str("\n").ok(); }
@@ -374,6 +2030,187 @@ impl Display for WaveFunction {
     }
 }
 
+// Each square tileset scrolling up for a few screens, speed rows a second
+pub fn scrolling (mut seed: u64, speed: f64) {
+    let demos: [fn(u64) -> WaveFunction; 5] = [ultima, maze, maze0, mobo, rogue];
+    loop {
+        for demo in demos {
+            let mut wf = demo(seed);
+            let rows = 4 * wf.term.h;
+            if let Err(e) = wf.scroll(rows, speed) {
+                wf.printResult(&Err(e));
+                sleep(3.0);
+            }
+        }
+        seed = seed.wrapping_add(1);
+    }
+}
+
+// A camera loop a few screens across and back, speed cells a second
+pub fn travelling (mut seed: u64, speed: f64) {
+    let demos: [fn(u64) -> WaveFunction; 4] = [ultima, maze0, mobo, hex];
+    loop {
+        for demo in demos {
+            let mut wf = demo(seed);
+            let (h, w) = (wf.term.h as isize, wf.term.w as isize);
+            if let Err(e) = wf.travel(&[(0, w), (h, 2 * w), (2 * h, w), (h, -w), (0, 0)], speed) {
+                wf.printResult(&Err(e));
+                sleep(3.0);
+            }
+        }
+        seed = seed.wrapping_add(1);
+    }
+}
+
+// World /////////////////////////////////////////////////////////////
+
+// Seed for the piece of a world of this kind at (x, y)
+fn placeSeed (seed: u64, kind: u64, x: isize, y: isize) -> u64 {
+    [kind, x as u64, y as u64].iter().fold(seed, |s, v| Rng::new(s ^ v).next_u64())
+}
+
+// Pin a block of states with its top left at (y, x), skipping any cells
+// that fall outside the grid
+fn pinBlock (wf: &mut WaveFunction, block: &[Vec<usize>], y: isize, x: isize) -> Result<(), WfcError> {
+    for (dy, row) in block.iter().enumerate() {
+        for (dx, s) in row.iter().enumerate() {
+            let (y, x) = (y + dy as isize, x + dx as isize);
+            if (0..wf.term.h as isize).contains(&y) && (0..wf.term.w as isize).contains(&x) {
+                wf.set(&Point::new(y as usize, x as usize), *s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Cells solved around a corner, and either side of an edge, so that the
+// chunks meeting there can all fit it
+const MARGIN: usize = 2;
+
+// An endless map in chunks of h rows by w columns.  A chunk is the same
+// for the same world seed and coordinates whichever chunks came before
+// it:  the corners where chunks meet are solved first, then the edges
+// between corners, then the insides between edges, each from a seed of
+// its own place.  A chunk's top row and left column are its edges, and
+// it's solved against the edges of the chunks below and right of it, so
+// neighbors always fit.  Square grids, and tilesets whose rules reach
+// only a few cells.  Rooms and the like that span more than the margin
+// need setSeams, or edges made without the chunks beside them can leave
+// no way to finish a chunk, and it fails every time.
+pub struct World {
+    tileset: fn() -> Vec<State>,
+    glyphs: Vec<Glyph>,
+    seed: u64,
+    size: (usize, usize),
+    seams: (Vec<usize>, Vec<usize>), // States allowed along top edges and left edges
+    gap: usize, // Cells from one place a passage may cross an edge to the next
+    chunks: HashMap<(isize, isize), Vec<Vec<usize>>> // State ids of the chunks made so far
+}
+
+impl World {
+    pub fn new (tileset: fn() -> Vec<State>, seed: u64, h: usize, w: usize) -> World {
+        assert!(2 * MARGIN < h.min(w), "chunks need more than {} rows and columns", 2 * MARGIN);
+        World {
+            tileset,
+            glyphs: tileset().iter().map(|s| s.glyph.clone()).collect(),
+            seed,
+            size: (h, w),
+            seams: ((0..tileset().len()).collect(), (0..tileset().len()).collect()),
+            gap: 1,
+            chunks: HashMap::new()
+        }
+    }
+    // Keep chunk edges to states that can't leave a chunk unfinishable,
+    // for a tileset of rooms say rock, with vertical passages across (top
+    // edges) and horizontal ones down (left edges).  Corners keep to the
+    // states both allow, as do the cells of an edge between crossings,
+    // which come every gap cells so that each passage has room for
+    // whatever it leads to.  Not passages along an edge, as one could only
+    // end at a wall on it.  Forgets the chunks made so far.
+    pub fn setSeams (&mut self, across: &[usize], down: &[usize], gap: usize) -> &mut Self {
+        assert!(across.iter().chain(down).all(|s| *s < self.glyphs.len()), "seam states out of range");
+        assert!(0 < gap, "gap must be at least 1");
+        self.seams = (across.to_vec(), down.to_vec());
+        self.gap = gap;
+        self.chunks.clear();
+        self
+    }
+    // State ids of chunk (cx, cy), rows of columns, made on first request.
+    // Chunk (cx + 1, cy) lies to its right and (cx, cy + 1) below it.
+    pub fn chunk (&mut self, cx: isize, cy: isize) -> Result<&Vec<Vec<usize>>, WfcError> {
+        if !self.chunks.contains_key(&(cx, cy)) {
+            let chunk = self.generate(cx, cy)?;
+            self.chunks.insert((cx, cy), chunk);
+        }
+        Ok(&self.chunks[&(cx, cy)])
+    }
+    // Let a chunk go, say once it's far away.  Asking again makes it anew,
+    // the same as before.
+    pub fn forget (&mut self, cx: isize, cy: isize) -> &mut Self {
+        self.chunks.remove(&(cx, cy));
+        self
+    }
+    pub fn glyph (&self, state: usize) -> &Glyph { &self.glyphs[state] }
+    // Solve h by w with blocks pinned, and cells kept to what seam allows
+    // them, if anything
+    fn solve (&self, seed: u64, h: usize, w: usize, blocks: &[(Vec<Vec<usize>>, isize, isize)], seam: impl Fn(&Point) -> Option<Vec<usize>>) -> Result<WaveFunction, WfcError> {
+        let mut wf = WaveFunction::with_seed((self.tileset)(), seed);
+        wf.setPlotting(false).setSize(h, w).setBoundary(Boundary::Open, Boundary::Open).setBacktracking(1000);
+        for p in wf.cells().collect::<Vec<_>>() {
+            let Some(allowed) = seam(&p) else { continue };
+            for s in (0..self.glyphs.len()).filter(|s| !allowed.contains(s)) { wf.ban(&p, s)?; }
+        }
+        for (block, y, x) in blocks { pinBlock(&mut wf, block, *y, *x)?; }
+        wf.solveRestarting(100)?;
+        Ok(wf)
+    }
+    // States both seams allow
+    fn shared (&self) -> Vec<usize> {
+        self.seams.0.iter().filter(|s| self.seams.1.contains(s)).copied().collect()
+    }
+    // States allowed i cells along a top edge (across) or left edge
+    fn seamAt (&self, across: bool, i: isize) -> Vec<usize> {
+        let crossing = self.gap / 2 == i.rem_euclid(self.gap as isize) as usize;
+        IF!(crossing, IF!(across, self.seams.0.clone(), self.seams.1.clone()), self.shared())
+    }
+    fn states (wf: &WaveFunction, ys: Range<usize>, xs: Range<usize>) -> Vec<Vec<usize>> {
+        ys.map(|y| xs.clone().map(|x| wf.stateAt(&Point::new(y, x))).collect()).collect()
+    }
+    // The cells around the top left corner of chunk (x, y), that corner in
+    // the middle.  All of them keep to what both seams allow, so nothing
+    // starts beside a seam that could only end on it.
+    fn corner (&self, x: isize, y: isize) -> Result<Vec<Vec<usize>>, WfcError> {
+        let n = 2 * MARGIN + 1;
+        let shared = self.shared();
+        let wf = self.solve(placeSeed(self.seed, 0, x, y), n, n, &[], |_| Some(shared.clone()))?;
+        Ok(World::states(&wf, 0..n, 0..n))
+    }
+    // Top row (across) or left column of chunk (x, y), corner to corner
+    fn edge (&self, x: isize, y: isize, across: bool) -> Result<Vec<Vec<usize>>, WfcError> {
+        let (m, n) = (MARGIN, IF!(across, self.size.1, self.size.0));
+        let (first, last) = (self.corner(x, y)?, IF!(across, self.corner(x + 1, y)?, self.corner(x, y + 1)?));
+        let seed = placeSeed(self.seed, IF!(across, 1, 2), x, y);
+        Ok(IF!(across, {
+            let wf = self.solve(seed, 2 * m + 1, n + 2 * m + 1, &[(first, 0, 0), (last, 0, n as isize)],
+                |p| IF!(m == p.y, Some(self.seamAt(true, p.x as isize - m as isize)), None))?;
+            World::states(&wf, m..m + 1, m..m + n + 1)
+        }, {
+            let wf = self.solve(seed, n + 2 * m + 1, 2 * m + 1, &[(first, 0, 0), (last, n as isize, 0)],
+                |p| IF!(m == p.x, Some(self.seamAt(false, p.y as isize - m as isize)), None))?;
+            World::states(&wf, m..m + n + 1, m..m + 1)
+        }))
+    }
+    fn generate (&self, cx: isize, cy: isize) -> Result<Vec<Vec<usize>>, WfcError> {
+        let (h, w, m) = (self.size.0 as isize, self.size.1 as isize, MARGIN as isize);
+        let blocks = [
+            (self.corner(cx, cy)?, -m, -m), (self.corner(cx + 1, cy)?, -m, w - m),
+            (self.corner(cx, cy + 1)?, h - m, -m), (self.corner(cx + 1, cy + 1)?, h - m, w - m),
+            (self.edge(cx, cy, true)?, 0, 0), (self.edge(cx, cy + 1, true)?, h, 0),
+            (self.edge(cx, cy, false)?, 0, 0), (self.edge(cx + 1, cy, false)?, 0, w)];
+        let wf = self.solve(placeSeed(self.seed, 3, cx, cy), self.size.0 + 1, self.size.1 + 1, &blocks, |_| None)?;
+        Ok(World::states(&wf, 0..self.size.0, 0..self.size.1))
+    }
+}
 
 // Main //////////////////////////////////////////////////////////////
 
@@ -385,155 +2222,232 @@ pub fn header () {
     println!("\x1b[35m   \\_/\\_/  |_|   \\____|\x1b[0m");
 }
 
-pub fn maze () -> WaveFunction {
-    let mut wf = WaveFunction::new(vec!(
-        State::new(0, ("\x1b[0;1;30;40m"," "), &[&[0,2,4,5,6,9],&[0,2,4,7,8,11],&[0,1,3,5,8,12],&[0,1,3,6,7,10]]),
-
-        State::new(1, ("\x1b[0;1;31;40m","|"), &[&[3,7,8,10,11,12],&[3,5,6,9,10,12],&[0],&[0]]),
-        State::new(2, ("\x1b[0;1;31;40m","-"), &[&[0],&[0],&[4,6,7,9,10,11],&[4,5,8,9,11,12]]),
+pub fn mazeStates () -> Vec<State> {
+    // Walls are segments between joints:  straight, corner or tee
+    let (blank, segment, joint, corner, tee) = (0, 1, 2, 3, 4);
+    let red = "\x1b[0;1;31;40m";
+    variants(&[
+        Tile::new(&[("\x1b[0;1;30;40m"," ")], Symmetry::X,
+            &[&[(blank,0),(segment,1),(joint,1),(corner,0),(corner,1),(tee,0)],&[],&[],&[]]),
+        // | -
+        Tile::new(&[(red,"|"),(red,"-")], Symmetry::I,
+            &[&[(joint,0),(corner,2),(corner,3),(tee,1),(tee,2),(tee,3)],&[],&[(blank,0)],&[]]),
+        // | -
+        Tile::new(&[(red,"|"),(red,"-")], Symmetry::I, &[&[(segment,0)],&[(segment,0)],&[(blank,0)],&[(blank,0)]]),
+        // _|  L  |^  7
+        Tile::new(&[(red,"+")], Symmetry::L, &[&[(segment,0)],&[(blank,0)],&[(blank,0)],&[(segment,1)]]),
+        // _|_  |-  ^|^  -|
+        Tile::new(&[(red,"-"),(red,"|"),(red,"-"),(red,"-")], Symmetry::T,
+            &[&[(segment,0)],&[(blank,0)],&[(segment,1)],&[(segment,1)]]),
+    ])
+}
 
-        // |
-        State::new(3, ("\x1b[0;1;31;40m","|"), &[&[1],&[1],&[0],&[0]]),
-        // -
-        State::new(4, ("\x1b[0;1;31;40m","-"), &[&[0],&[0],&[2],&[2]]),
-
-        // L
-        State::new(5, ("\x1b[0;1;31;40m","+"), &[&[1],&[0],&[2],&[0]]),
-        // _|
-        State::new(6, ("\x1b[0;1;31;40m","+"), &[&[1],&[0],&[0],&[2]]),
-        // 7
-        State::new(7, ("\x1b[0;1;31;40m","+"), &[&[0],&[1],&[0],&[2]]),
-        // |^
-        State::new(8, ("\x1b[0;1;31;40m","+"), &[&[0],&[1],&[2],&[0]]),
-
-        // _|_
-        State::new(9, ("\x1b[0;1;31;40m","-"), &[&[1],&[0],&[2],&[2]]),
-        // -|
-        State::new(10, ("\x1b[0;1;31;40m","-"), &[&[1],&[1],&[0],&[2]]),
-        // ^|^
-        State::new(11, ("\x1b[0;1;31;40m","-"), &[&[0],&[1],&[2],&[2]]),
-        //  |-
-        State::new(12, ("\x1b[0;1;31;40m","|"), &[&[1],&[1],&[2],&[0]]),
-
-    ));
-    while wf.collapseMaybe() { }
-    print!("{HOM}{RST}\n");
-    if !true {
-        print!("\x1b[H");
-        wf.print();
-        wf.resetrow();
-        for _ in 0..100 {
-            while wf.collapseMaybe() { }
-            print!("\x1b[H\x1bM");
-            wf.printTop();
-            wf.resetrow();
-        }
-    }
+pub fn maze (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(mazeStates(), seed);
+    wf.setBacktracking(1000);
+    wf.setConnected(&[1,2,3,4,5,6,7,8,9,10,11,12]); // One network of passages
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
     wf
 }
 
-pub fn maze0 () -> WaveFunction {
-    let mut wf = WaveFunction::new(vec!(
-        State::new(0, ("\x1b[0;40m",   " "), &[&[0,2,4,7,8,9,15,16,17],&[0,2,4,5,6,11,14,16,17],&[0,1,3,5,7,12,14,15,16],&[0,1,3,6,8,10,14,15,17]]),
-        // |
-        State::new(1, ("\x1b[0;44;1;34m","|"), &[&[3,5,6,10,11,12,13,14],&[3,7,8,9,10,12,13,15],&[0],&[0]]),
-        // -
-        State::new(2, ("\x1b[0;44;1;34m","-"), &[&[0],&[0],&[4,6,8,9,10,11,13,17],&[4,5,7,9,11,12,13,16]]),
-        // |
+pub fn maze0States () -> Vec<State> {
+    // Walls are segments between joints:  straight, corner, tee, cross or
+    // a dead end
+    let (blank, segment, joint, corner, tee, cross, end) = (0, 1, 2, 3, 4, 5, 6);
+    let blue = "\x1b[0;44;1;34m";
+    variants(&[
+        Tile::new(&[("\x1b[0;40m"," ")], Symmetry::X,
+            &[&[(blank,0),(segment,1),(joint,1),(corner,0),(corner,1),(tee,0),(end,0),(end,1),(end,3)],&[],&[],&[]]),
+        // | -
+        Tile::new(&[(blue,"|"),(blue,"-")], Symmetry::I,
+            &[&[(joint,0),(corner,2),(corner,3),(tee,1),(tee,2),(tee,3),(cross,0),(end,2)],&[],&[(blank,0)],&[]]),
+        // |    -*-
         // *
         // |
-        State::new(3, ("\x1b[0;44;1;34m","+"), &[&[1],&[1],&[0],&[0]]),
-        // -*-
-        State::new(4, ("\x1b[0;44;1;34m","+"), &[&[0],&[0],&[2],&[2]]),
-        // *-
-        // |
-        State::new(5, ("\x1b[0;44;1;34m","+"), &[&[0],&[1],&[2],&[0]]),
-        // -*
-        //  |
-        State::new(6, ("\x1b[0;44;1;34m","+"), &[&[0],&[1],&[0],&[2]]),
-        //  |
-        //  *-
-        State::new(7, ("\x1b[0;44;1;34m","+"), &[&[1],&[0],&[2],&[0]]),
-        //  |
-        // -*
-        State::new(8, ("\x1b[0;44;1;34m","+"), &[&[1],&[0],&[0],&[2]]),
-        //  |
-        // -*-
-        State::new(9, ("\x1b[0;44;1;34m","+"), &[&[1],&[0],&[2],&[2]]),
+        Tile::new(&[(blue,"+")], Symmetry::I, &[&[(segment,0)],&[(segment,0)],&[(blank,0)],&[(blank,0)]]),
         //  |
         // -*
+        Tile::new(&[(blue,"+")], Symmetry::L, &[&[(segment,0)],&[(blank,0)],&[(blank,0)],&[(segment,1)]]),
         //  |
-        State::new(10, ("\x1b[0;44;1;34m","+"), &[&[1],&[1],&[0],&[2]]),
         // -*-
-        //  |
-        State::new(11, ("\x1b[0;44;1;34m","+"), &[&[0],&[1],&[2],&[2]]),
-        //  |
-        //  *-
-        //  |
-        State::new(12, ("\x1b[0;44;1;34m","+"), &[&[1],&[1],&[2],&[0]]),
+        Tile::new(&[(blue,"+")], Symmetry::T, &[&[(segment,0)],&[(blank,0)],&[(segment,1)],&[(segment,1)]]),
         //  |
         // -*-
         //  |
-        State::new(13, ("\x1b[0;44;1;34m","+"), &[&[1],&[1],&[2],&[2]]),
-        //  *
-        //  |
-        State::new(14, ("\x1b[00;;44;1;34m","+"), &[&[0],&[1],&[0],&[0]]),
+        Tile::new(&[(blue,"+")], Symmetry::X, &[&[(segment,0)],&[],&[],&[]]),
         //  |
         //  *
-        State::new(15, ("\x1b[0;44;1;34m","+"), &[&[1],&[0],&[0],&[0]]),
-        //  *-
-        State::new(16, ("\x1b[0;44;1;34m","+"), &[&[0],&[0],&[2],&[0]]),
-        //  -*
-        State::new(17, ("\x1b[0;44;1;34m","+"), &[&[0],&[0],&[2],&[0]]),
-
-    ));
-    while wf.collapseMaybe() { }
-    print!("{HOM}{RST}\n");
-    if !true {
-        print!("\x1b[H");
-        wf.print();
-        wf.resetrow();
-        for _ in 0..100 {
-            while wf.collapseMaybe() { }
-            print!("\x1b[H\x1bM");
-            wf.printTop();
-            wf.resetrow();
+        Tile::new(&[(blue,"+")], Symmetry::T, &[&[(segment,0)],&[(blank,0)],&[(blank,0)],&[(blank,0)]]),
+    ])
+}
+
+pub fn maze0 (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(maze0States(), seed);
+    wf.setBacktracking(1000);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    wf
+}
+
+
+
+pub fn ultimaStates () -> Vec<State> {
+    vec!(
+        State::new(0, ("\x1b[0;34m", &BLK.to_string()), &[&[0,1],  &[0,1],  &[0,1],  &[0,1]]).weighted(6.0),
+        State::new(1, ("\x1b[0;1;34m", &BLK.to_string()), &[&[0,1,2],&[0,1,2],&[0,1,2],&[0,1,2]]).weighted(3.0),
+        State::new(2, ("\x1b[0;33m", &BLK.to_string()), &[&[1,2,3],&[1,2,3],&[1,2,3],&[1,2,3]]).weighted(1.0),
+        State::new(3, ("\x1b[0;1;32m", &BLK.to_string()), &[&[2,3,4],&[2,3,4],&[2,3,4],&[2,3,4]]).weighted(3.0),
+        State::new(4, ("\x1b[0;37m", &BLK.to_string()), &[&[3,4,5],&[3,4,5],&[3,4,5],&[3,4,5]]).weighted(1.0),
+        State::new(5, ("\x1b[0;1;37m", &BLK.to_string()), &[&[4,5],  &[4,5],  &[4,5],  &[4,5]]).weighted(0.3),
+    )
+}
+
+pub fn ultima (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(ultimaStates(), seed);
+    wf.setHeuristic(Heuristic::Entropy);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    wf
+}
+
+// Coarse zones for ultima:  ocean, land and mountains
+pub fn continentStates () -> Vec<State> {
+    vec!(
+        State::new(0, ("\x1b[0;34m", &BLK.to_string()), &[&[0,1],  &[0,1],  &[0,1],  &[0,1]]).weighted(2.0),
+        State::new(1, ("\x1b[0;1;32m", &BLK.to_string()), &[&[0,1,2],&[0,1,2],&[0,1,2],&[0,1,2]]).weighted(2.0),
+        State::new(2, ("\x1b[0;37m", &BLK.to_string()), &[&[1,2],  &[1,2],  &[1,2],  &[1,2]]),
+    )
+}
+
+// ultima detailed from a continentStates map, a coarse cell every five
+// cells each way
+pub fn continents (seed: u64) -> WaveFunction {
+    let scale = 5;
+    let mut wf = WaveFunction::with_seed(ultimaStates(), seed);
+    let mut coarse = WaveFunction::with_seed(continentStates(), seed);
+    coarse.setPlotting(false).setSize(wf.term.h / scale, wf.term.w / scale).setHeuristic(Heuristic::Entropy);
+    let result = coarse.solveRestarting(100).and_then(|_| {
+        wf.setHeuristic(Heuristic::Entropy).setBacktracking(1000);
+        wf.refine(&coarse, scale, &[&[0, 1], &[2, 3], &[4, 5]], 1);
+        wf.solveRestarting(100)
+    });
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    wf
+}
+
+// Chunks of an endless ultima filling the screen, made in a scattered
+// order since they fit together whatever the order
+pub fn world (seed: u64) -> World {
+    let term = Term::new();
+    let (h, w) = (12, 24);
+    let mut world = World::new(ultimaStates, seed, h, w);
+    let mut order: Vec<(usize, usize)> = (0..term.h.div_ceil(h)).flat_map(|cy| (0..term.w.div_ceil(w)).map(move |cx| (cx, cy))).collect();
+    let mut rng = Rng::new(seed);
+    (1..order.len()).rev().for_each(|i| order.swap(i, rng.below(i + 1)));
+    for (cx, cy) in order {
+        match world.chunk(cx as isize, cy as isize).cloned() {
+            Ok(chunk) => {
+                chunk.iter().enumerate().for_each(|(y, row)| row.iter().enumerate().for_each(|(x, s)| {
+                    let (y, x) = (cy * h + y, cx * w + x);
+                    if y < term.h && x < term.w { print!("\x1b[{};{}H{}", y + 1, x + 1, world.glyph(*s).glyph()); }
+                }));
+            }
+            Err(e) => print!("\x1b[{};{}H\x1b[1;37;41m{}", cy * h + 1, cx * w + 1, e)
         }
+        stdout().flush().ok();
+        sleep(0.1);
     }
+    print!("\x1b[{}H\x1b[1;37;41mseed {} chunks {}x{}\x1b[0m", term.h, seed, h, w);
+    println!("{HOM}{RST}");
+    world
+}
+
+// ultima on hexes, from ocean up to mountains
+pub fn hexStates () -> Vec<State> {
+    let glf = format!("{BLK} ");
+    vec!(
+        State::new(0, ("\x1b[0;34m", &glf), &[&[0,1][..]; 6]).weighted(6.0),
+        State::new(1, ("\x1b[0;1;34m", &glf), &[&[0,1,2][..]; 6]).weighted(3.0),
+        State::new(2, ("\x1b[0;33m", &glf), &[&[1,2,3][..]; 6]).weighted(1.0),
+        State::new(3, ("\x1b[0;1;32m", &glf), &[&[2,3,4][..]; 6]).weighted(3.0),
+        State::new(4, ("\x1b[0;32m", &glf), &[&[3,4,5][..]; 6]).weighted(2.0),
+        State::new(5, ("\x1b[0;37m", &glf), &[&[4,5,6][..]; 6]).weighted(1.0),
+        State::new(6, ("\x1b[0;1;37m", &glf), &[&[5,6][..]; 6]).weighted(0.3),
+    )
+}
+
+pub fn hex (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_topology(hexStates(), Hex, seed);
+    // Two columns a hex plus the odd rows' indent, and an even row count to wrap
+    let (h, w) = (wf.term.h & !1, wf.term.w.saturating_sub(1) / 2);
+    wf.setSize(h, w).setHeuristic(Heuristic::Entropy);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
     wf
 }
 
+// Rock, air, and floor, the air just above rock.  Rock meets air sideways
+// only across floor.  Directions as on Cube, the last two the layers above
+// and below.
+pub fn caveStates () -> Vec<State> {
+    let (rock, air, floor) = (0, 1, 2);
+    let (r, a, f): (&[usize], &[usize], &[usize]) = (&[rock, floor], &[air, floor], &[rock, air, floor]);
+    vec!(
+        State::new(rock, ("\x1b[0;37m", &BLK.to_string()), &[r, r, r, r, &[rock, floor], &[rock, air]]).weighted(3.0),
+        State::new(air, ("\x1b[0m", " "), &[a, a, a, a, &[air, rock], &[air, floor]]).weighted(2.0),
+        State::new(floor, ("\x1b[0;33m", "."), &[f, f, f, f, &[air], &[rock]]),
+    )
+}
+
+// A volume of caves with rock below and above.  The lowest layer is plotted
+// as it's solved, then each layer above is shown in turn.
+pub fn caves (seed: u64) -> WaveFunction {
+    let depth = 4;
+    let mut wf = WaveFunction::with_topology(caveStates(), Cube, seed);
+    wf.setLayers(depth, Boundary::Fixed(0)).setHeuristic(Heuristic::Entropy).setBacktracking(1000);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    (1..depth).for_each(|z| {
+        sleep(1.5);
+        print!("{HOM}");
+        wf.setLayer(z).print().printResult(&result);
+    });
+    println!("{HOM}{RST}");
+    wf
+}
 
+// Sea, beach, grass and forest.  Corners follow the same rules as sides, so
+// the sea never touches grass diagonally either.  For Moore.
+pub fn islandStates () -> Vec<State> {
+    let (sea, beach, grass, forest) = (0, 1, 2, 3);
+    let next: [&[usize]; 4] = [&[sea, beach], &[sea, beach, grass], &[beach, grass, forest], &[grass, forest]];
+    let glyphs = [("\x1b[0;34m", "~"), ("\x1b[0;1;33m", "."), ("\x1b[0;1;32m", "\""), ("\x1b[0;32m", "T")];
+    let weights = [1.0, 1.0, 3.0, 2.0];
+    (0..4).map(|s| State::new(s, glyphs[s], &[next[s]; 4]).diagonals(&[next[s]; 4]).weighted(weights[s])).collect()
+}
 
-pub fn ultima () -> WaveFunction {
-    let mut wf = WaveFunction::new(vec!(
-        State::new(0, ("\x1b[0;34m", &BLK.to_string()), &[&[0,1],  &[0,1],  &[0,1],  &[0,1]]),
-        State::new(1, ("\x1b[0;1;34m", &BLK.to_string()), &[&[0,1,2],&[0,1,2],&[0,1,2],&[0,1,2]]),
-        State::new(2, ("\x1b[0;33m", &BLK.to_string()), &[&[1,2,3],&[1,2,3],&[1,2,3],&[1,2,3]]),
-        State::new(3, ("\x1b[0;1;32m", &BLK.to_string()), &[&[2,3,4],&[2,3,4],&[2,3,4],&[2,3,4]]),
-        State::new(4, ("\x1b[0;37m", &BLK.to_string()), &[&[3,4,5],&[3,4,5],&[3,4,5],&[3,4,5]]),
-        State::new(5, ("\x1b[0;1;37m", &BLK.to_string()), &[&[4,5],  &[4,5],  &[4,5],  &[4,5]]),
-    ));
-    while wf.collapseMaybe() { }
-    print!("{HOM}{RST}\n");
-    if !true {
-        print!("\x1b[H");
-        wf.print();
-        wf.resetrow();
-        for _ in 0..100 {
-            while wf.collapseMaybe() { }
-            if !true {
-                print!("\x1b[H\x1bM");
-                wf.printTop();
-                wf.resetrow();
-           }
-        }
+pub fn islands (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_topology(islandStates(), Moore, seed);
+    wf.setHeuristic(Heuristic::Entropy).setBacktracking(1000);
+    // Left alone it settles on all sea or all land, so plant both
+    let (h, w) = (wf.term.h, wf.term.w);
+    for i in 0..6 {
+        wf.set(&Point::new(h * (1 + 2 * (i % 3)) / 6, w * (1 + 2 * i) / 12), i % 2 * 2).ok();
     }
+    let result = wf.solve().map(|()| 1);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
     wf
 }
 
-pub fn mobo () -> WaveFunction {
-    let mut wf = WaveFunction::new(vec!(
+pub fn moboStates () -> Vec<State> {
+    vec!(
         // outside space D A/B C
         State::new(0, ("\x1b[0;42;32m"," "), &[&[0,3,4,6,11],&[0,1,2,5,11],&[0,1,3,8,10],&[0,2,4,7,10]]),
 
@@ -566,25 +2480,20 @@ pub fn mobo () -> WaveFunction {
 
         // crossroad path
         State::new(12, ("\x1b[0;1;42;32m","+"), &[&[10],&[10],&[11],&[11]]),
-    ));
-    while wf.collapseMaybe() {  }
-    print!("{HOM}{RST}\n");
-    if !true {
-        wf.resetrow();
-        for _ in 0..100 {
-            while wf.collapseMaybe() { }
-            if true {
-                print!("\x1b[H\x1bM");
-                wf.printTop();
-                wf.resetrow();
-                readline();
-            }
-        }
-    }
+    )
+}
+
+pub fn mobo (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(moboStates(), seed);
+    wf.setBacktracking(1000);
+    wf.setCount(12, 1, 3); // A few crossroads
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
     wf
 }
-pub fn rogue () -> WaveFunction {
-    let mut wf = WaveFunction::new(vec!(
+pub fn rogueStates () -> Vec<State> {
+    vec!(
         // outside space D A/B C
         State::new(0, ("\x1b[0;40;32m",":"), &[&[0,3,4,6,11],&[0,1,2,5,11],&[0,1,3,8,10],&[0,2,4,7,10]]),
 
@@ -617,35 +2526,162 @@ pub fn rogue () -> WaveFunction {
 
         // crossroad path
         State::new(12, ("\x1b[0;40;36m","#"), &[&[10],&[10],&[11],&[11]]),
-    ));
-    while wf.collapseMaybe() {  }
-    print!("{HOM}{RST}\n");
-    if !true {
-        for _ in 0..100 {
-            wf.resetrow();
-            while wf.collapseMaybe() { }
-            if true {
-                print!("\x1b[H\x1bM");
-                wf.printTop();
-                //readline();
-                //sleep(0.2);
-            }
-        }
-    }
+    )
+}
+
+pub fn rogue (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(rogueStates(), seed);
+    wf.setBoundary(Boundary::Fixed(0), Boundary::Fixed(0)); // Solid rock all around
+    wf.setBacktracking(1000);
+    wf.setConnected(&[1,2,3,4,5,6,7,8,9,10,11,12]); // Every room reachable
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    wf
+}
+
+// rogue's rock and passages, with the rooms as big tiles so they always
+// come out whole
+pub fn dungeonStates () -> Vec<State> {
+    let (rock, vertical, horizontal, cross) = (0, 1, 2, 3);
+    let red = "\x1b[0;1;40;31m";
+    // Rock all around, and passages through the walls between the corners
+    let room = |rows: &[&str]| {
+        let (h, w) = (rows.len(), rows[0].len());
+        BigTile::new(red, rows, &[&[rock][..]; 4])
+            .opening(0, 1..w-1, &[vertical]).opening(1, 1..w-1, &[vertical])
+            .opening(2, 1..h-1, &[horizontal]).opening(3, 1..h-1, &[horizontal])
+    };
+    bigTiles(vec!(
+        State::new(rock, ("\x1b[0;40;32m",":"), &[&[rock,horizontal],&[rock,horizontal],&[rock,vertical],&[rock,vertical]]),
+        State::new(vertical, ("\x1b[0;40;36m","#"), &[&[vertical,cross],&[vertical,cross],&[rock],&[rock]]),
+        State::new(horizontal, ("\x1b[0;1;40;34m","="), &[&[rock],&[rock],&[horizontal,cross],&[horizontal,cross]]),
+        State::new(cross, ("\x1b[0;40;36m","#"), &[&[vertical],&[vertical],&[horizontal],&[horizontal]]),
+    ), &[
+        room(&[
+            "#-------#",
+            "|.......|",
+            "|.......|",
+            "|.......|",
+            "#-------#"]),
+        room(&[
+            "#----#",
+            "|....|",
+            "|....|",
+            "#----#"]),
+        room(&[
+            "#-----------#",
+            "|...........|",
+            "|...........|",
+            "|...........|",
+            "|...........|",
+            "|...........|",
+            "#-----------#"]).weighted(0.5),
+    ])
+}
+
+pub fn dungeon (seed: u64) -> WaveFunction {
+    let mut wf = WaveFunction::with_seed(dungeonStates(), seed);
+    wf.setBoundary(Boundary::Fixed(0), Boundary::Fixed(0));
+    wf.setBacktracking(1000);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    wf
+}
+
+// Sample for the overlapping model
+pub const HOUSES: &str = "\
+..............
+..+------+....
+..|      |....
+..|      +---+
+..+--+   |   |
+.....|   |   |
+.....+---+---+
+..............
+";
+
+pub fn houses (seed: u64) -> WaveFunction {
+    // Mirror images only, since turning would swap | and -
+    let mut wf = WaveFunction::with_seed(overlapping(HOUSES, 3, 2, true), seed);
+    wf.setBacktracking(1000);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
     wf
 }
 
+// Learn NxN patterns from a sample in a text file, wrapping around its
+// edges, and draw a map from them.  Mirror images and turns aren't added,
+// since they would swap characters like | and -.
+pub fn sampleFile (path: &str, n: usize) -> Res<Vec<State>> {
+    if 0 == n { return Err("pattern size must be at least 1".into()) }
+    let sample = fs::read_to_string(path)?;
+    if sample.lines().all(|l| l.is_empty()) { return Err(format!("{} is empty", path).into()) }
+    Ok(overlapping(&sample, n, 1, true))
+}
+
+pub fn sampled (path: &str, n: usize, seed: u64) -> Res<WaveFunction> {
+    let mut wf = WaveFunction::with_seed(sampleFile(path, n)?, seed);
+    wf.setBacktracking(1000);
+    let result = wf.solveRestarting(100);
+    wf.printResult(&result);
+    println!("{HOM}{RST}");
+    Ok(wf)
+}
+
 pub fn main () {
-    print!("USAGE:  wavefunctioncollapse [HEIGHT default 25] [WIDTH default 80]");
+    print!("USAGE:  wavefunctioncollapse [HEIGHT default 25] [WIDTH default 80] [SEED default clock] [caves [VOXEL FILE] | scroll [ROWS A SECOND default 10] | travel [CELLS A SECOND default 20] | sample FILE [N default 3]]");
     print!("{SAV}{HOM}{CLR}");
     header();
     //print!("HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\n\r\n", (wf.term.w+1)*wf.term.h);
+    let mut seed = env::args().nth(3).and_then(|s| s.parse::<u64>().ok()).unwrap_or_else(clockseed);
+    if Some("caves") == env::args().nth(4).as_deref() {
+        let voxels = caves(seed).browse().voxels();
+        // The voxel dump to a file if given, else on screen
+        match env::args().nth(5) {
+            Some(path) => match fs::write(&path, voxels) {
+                Ok(()) => println!("{HOM}{CLR}voxels written to {}", path),
+                Err(e) => println!("{HOM}{CLR}{}: {}", path, e)
+            },
+            None => print!("{HOM}{CLR}{}", voxels)
+        }
+        return
+    }
+    if Some("sample") == env::args().nth(4).as_deref() {
+        let path = env::args().nth(5).unwrap_or_default();
+        let n = env::args().nth(6).and_then(|s| s.parse::<usize>().ok()).unwrap_or(3);
+        match sampled(&path, n, seed) {
+            Ok(wf) => println!("\x1b[{}H", wf.term.h + 1),
+            Err(e) => println!("{}{CLR}sample {}: {}", HOM, path, e)
+        }
+        return
+    }
+    if Some("scroll") == env::args().nth(4).as_deref() {
+        let speed = env::args().nth(5).and_then(|s| s.parse::<f64>().ok()).unwrap_or(10.0);
+        if 0.0 < speed { scrolling(seed, speed) } else { println!("{}{CLR}scroll: speed must be positive, not {}", HOM, speed) }
+        return
+    }
+    if Some("travel") == env::args().nth(4).as_deref() {
+        let speed = env::args().nth(5).and_then(|s| s.parse::<f64>().ok()).unwrap_or(20.0);
+        if 0.0 < speed { travelling(seed, speed) } else { println!("{}{CLR}travel: speed must be positive, not {}", HOM, speed) }
+        return
+    }
     loop {
-        maze(); sleep(3.0);
-        ultima(); sleep(3.0);
-        maze0(); sleep(3.0);
-        mobo(); sleep(3.0);
-        rogue(); sleep(3.0);
+        maze(seed); sleep(3.0);
+        ultima(seed); sleep(3.0);
+        continents(seed); sleep(3.0);
+        world(seed); sleep(3.0);
+        hex(seed); sleep(3.0);
+        islands(seed); sleep(3.0);
+        maze0(seed); sleep(3.0);
+        mobo(seed); sleep(3.0);
+        rogue(seed); sleep(3.0);
+        dungeon(seed); sleep(3.0);
+        houses(seed); sleep(3.0);
+        caves(seed); sleep(3.0);
+        seed = seed.wrapping_add(1);
     }
     //print!("\x1b[H{}\r", wf);
     //print!("\x1b[{}H\x1b[1;37;41m{}\x1b[0m", wf.term.h, wf.info);
@@ -682,4 +2718,750 @@ collapsing a QS will
    superpose its neighbors with their orthogonal neighbors
 
 superpose and self with orthogonal states' constraints
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_stays_in_range() {
+        let mut rng = Rng::new(42);
+        (1..50).for_each(|n| {
+            let mut seen = vec![false; n];
+            (0..20 * n).for_each(|_| {
+                let i = rng.below(n);
+                assert!(i < n);
+                seen[i] = true;
+            });
+            assert!(seen.iter().all(|s| *s));
+        });
+        (0..10000).for_each(|_| assert!((0.0..1.0).contains(&rng.unit())));
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let builds: [fn(u64) -> WaveFunction; 11] = [
+            |seed| WaveFunction::with_seed(mazeStates(), seed),
+            |seed| WaveFunction::with_seed(maze0States(), seed),
+            |seed| WaveFunction::with_seed(ultimaStates(), seed),
+            |seed| WaveFunction::with_seed(continentStates(), seed),
+            |seed| WaveFunction::with_seed(moboStates(), seed),
+            |seed| WaveFunction::with_seed(rogueStates(), seed),
+            |seed| WaveFunction::with_seed(dungeonStates(), seed),
+            |seed| WaveFunction::with_seed(overlapping(HOUSES, 3, 2, true), seed),
+            |seed| WaveFunction::with_topology(hexStates(), Hex, seed),
+            |seed| WaveFunction::with_topology(islandStates(), Moore, seed),
+            |seed| {
+                let mut wf = WaveFunction::with_topology(caveStates(), Cube, seed);
+                wf.setLayers(3, Boundary::Fixed(0));
+                wf
+            }];
+        let map = |build: fn(u64) -> WaveFunction, seed: u64| {
+            let mut wf = build(seed);
+            wf.setPlotting(false).setSize(12, 24).setBacktracking(100);
+            wf.solve().ok();
+            (wf.text(), format!("{:?}", wf.grid)) // Ultima's glyphs differ only in color
+        };
+        builds.iter().for_each(|build| assert_eq!(map(*build, 42), map(*build, 42)));
+        assert_ne!(map(builds[0], 42).0, map(builds[0], 43).0);
+        assert_ne!(map(builds[2], 42).1, map(builds[2], 43).1);
+    }
+
+    #[test]
+    fn weights_bias_the_collapse() {
+        // Each state drawn in proportion to its weight
+        let states = ultimaStates();
+        let total: f64 = states.iter().map(|s| s.weight).sum();
+        let mut rng = Rng::new(1);
+        let mut drawn = [0.0; 6];
+        (0..100000).for_each(|_| {
+            let mut ss = SuperState::from(0..6);
+            ss.collapse(&mut rng, |id| states[id].weight);
+            drawn[ss.state()] += 1.0;
+        });
+        states.iter().for_each(|s| assert!((drawn[s.id] / 100000.0 - s.weight / total).abs() < 0.01, "{:?}", drawn));
+        // Heavy ocean and light snow show in whole maps, not just single cells
+        let counts = |uniform: bool| (0..4).fold([0; 6], |mut counts, seed| {
+            let states = ultimaStates().into_iter().map(|s| IF!(uniform, s.weighted(1.0), s)).collect();
+            let mut wf = WaveFunction::with_seed(states, seed);
+            wf.setPlotting(false).setSize(20, 40);
+            assert!(wf.solveRestarting(10).is_ok());
+            wf.grid.iter().flatten().for_each(|ss| counts[ss.state()] += 1);
+            counts
+        });
+        let (weighted, uniform) = (counts(false), counts(true));
+        assert!(10 * uniform[0] < weighted[0] && 10 * weighted[5] < uniform[5], "{:?} {:?}", weighted, uniform);
+    }
+
+    #[test]
+    fn zero_weight_states_wait_for_the_others() {
+        let weights = [0.0, 1.0, 0.0, 2.0];
+        let mut rng = Rng::new(1);
+        (0..10000).for_each(|_| {
+            let mut ss = SuperState::from(0..4);
+            ss.collapse(&mut rng, |id| weights[id]);
+            assert!([1, 3].contains(&ss.state()));
+        });
+        // Only zero weights left, so any of them
+        let mut ss = SuperState::from([0, 2].into_iter());
+        ss.collapse(&mut rng, |id| weights[id]);
+        assert!([0, 2].contains(&ss.state()));
+        let mut wf = WaveFunction::with_seed(ultimaStates().into_iter().map(|s| IF!(5 == s.id, s.weighted(0.0), s)).collect(), 1);
+        wf.setPlotting(false).setSize(50, 100);
+        assert!(wf.solveRestarting(10).is_ok());
+        assert!(wf.grid.iter().flatten().all(|ss| 5 != ss.state()));
+    }
+
+    #[test]
+    fn entropy_heuristic_picks_the_lowest() {
+        let shannon = |wf: &WaveFunction, p: &Point| {
+            let weights: Vec<f64> = wf.ss_ref(p).states().map(|s| wf.basestates[s].weight).collect();
+            let total: f64 = weights.iter().sum();
+            -weights.iter().map(|w| w / total * (w / total).ln()).sum::<f64>()
+        };
+        let mut wf = WaveFunction::with_seed(ultimaStates(), 2);
+        wf.setPlotting(false).setSize(20, 40).setHeuristic(Heuristic::Entropy);
+        let mut picks = 0;
+        while let Some(p) = wf.getLowestEntropy() {
+            let lowest = wf.cells().filter(|q| wf.is_superpositioned(q)).map(|q| shannon(&wf, &q)).fold(f64::MAX, f64::min);
+            assert!((shannon(&wf, &p) - lowest).abs() < 1e-5, "{:?} at pick {}", p, picks);
+            if wf.collapseAt(&p).is_none() { break }
+            picks += 1;
+        }
+        assert!(100 < picks);
+    }
+
+    #[test]
+    fn backtracking_recovers_from_contradictions() {
+        let maze = |seed: u64, budget: Option<usize>| {
+            let mut wf = WaveFunction::with_seed(mazeStates(), seed);
+            wf.setPlotting(false).setSize(20, 40);
+            if let Some(budget) = budget { wf.setBacktracking(budget); }
+            let result = wf.solve();
+            (result, wf.backtracks(), wf.text())
+        };
+        // Without it maze always runs into a contradiction, with it most
+        // seeds solve without a hole
+        assert!((0..8).all(|seed| maze(seed, None).0.is_err()));
+        let solved: Vec<u64> = (0..8).filter(|seed| {
+            let (result, used, text) = maze(*seed, Some(1000));
+            assert!(result.is_ok() || Err(WfcError::BacktrackLimit(1000)) == result);
+            result.is_ok() && 0 < used && !text.contains(['*', '!'])
+        }).collect();
+        assert!(6 <= solved.len(), "{:?}", solved);
+        // Out of budget
+        let (result, used, _) = maze(3, Some(1));
+        assert_eq!((Err(WfcError::BacktrackLimit(1)), 1), (result, used));
+    }
+
+    #[test]
+    fn restarting_moves_on_to_the_next_seed() {
+        // Maze without backtracking needs many tries, each on the next seed
+        let mut wf = WaveFunction::with_seed(mazeStates(), 0);
+        wf.setPlotting(false).setSize(20, 40);
+        let attempts = wf.solveRestarting(100).expect("maze solves within 100 seeds");
+        assert!(1 < attempts);
+        assert_eq!(attempts as u64 - 1, wf.seed());
+        assert!(!wf.text().contains(['*', '!']));
+        // The same as starting on the seed that worked
+        let mut again = WaveFunction::with_seed(mazeStates(), wf.seed());
+        again.setPlotting(false).setSize(20, 40);
+        assert_eq!((Ok(1), wf.text()), (again.solveRestarting(1), again.text()));
+        // Out of tries
+        let mut wf = WaveFunction::with_seed(mazeStates(), 0);
+        wf.setPlotting(false).setSize(20, 40);
+        assert_eq!(Err(WfcError::AttemptLimit(attempts - 1)), wf.solveRestarting(attempts - 1));
+        // Impossible whatever the seed, so said at once
+        let mut wf = WaveFunction::with_seed(ultimaStates(), 0);
+        wf.setPlotting(false).setSize(20, 40).setCount(0, 801, usize::MAX);
+        assert_eq!(Err(WfcError::CountLimit(0)), wf.solveRestarting(5));
+        assert_eq!(0, wf.seed());
+    }
+
+    #[test]
+    fn ultima_1000x1000_propagates_without_overflow() {
+        let mut wf = WaveFunction::with_seed(ultimaStates(), 1);
+        wf.setPlotting(false).setSize(1000, 1000);
+        assert!(wf.solveRestarting(10).is_ok());
+        assert!(wf.grid.iter().flatten().all(|ss| 1 == ss.count()));
+    }
+
+    #[test]
+    fn long_propagation_chain_without_overflow() {
+        // Alternating along a 1 row strip, so one collapse settles every
+        // cell, each through the last.  Recursion overflowed the stack here.
+        let strip = || vec![
+            State::new(0, ("", "A"), &[&[0, 1], &[0, 1], &[1], &[1]]),
+            State::new(1, ("", "B"), &[&[0, 1], &[0, 1], &[0], &[0]])];
+        [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| {
+            let mut wf = WaveFunction::with_seed(strip(), 1);
+            wf.setPlotting(false).setSize(1, 200_000).setPropagator(*propagator);
+            assert!(wf.collapseMaybe());
+            assert_eq!(200_000, wf.rowcount[0]);
+            assert!(!wf.collapseMaybe());
+            assert_eq!(Ok(()), wf.solve());
+        });
+    }
+
+    fn solved(states: fn() -> Vec<State>, seed: u64, propagator: Propagator, backtracking: bool) -> Option<Vec<usize>> {
+        let mut wf = WaveFunction::with_seed(states(), seed);
+        wf.setPlotting(false).setSize(30, 60).setPropagator(propagator);
+        if backtracking { wf.setBacktracking(100); }
+        wf.solve().ok()?;
+        Some(wf.grid.iter().flatten().map(|ss| ss.state()).collect())
+    }
+
+    #[test]
+    fn ac4_matches_projection() {
+        let tilesets = [mazeStates as fn() -> Vec<State>, maze0States, ultimaStates, moboStates, rogueStates];
+        let mut compared = 0;
+        [false, true].iter().for_each(|backtracking|
+            tilesets.iter().for_each(|states| (0..8).for_each(|seed| {
+                let projection = solved(*states, seed, Propagator::Projection, *backtracking);
+                let ac4 = solved(*states, seed, Propagator::Ac4, *backtracking);
+                if projection.is_some() { compared += 1; }
+                assert_eq!(projection, ac4);
+            })));
+        assert!(40 < compared);
+    }
+
+    #[test]
+    fn ac4_matches_projection_by_entropy() {
+        (0..8).for_each(|seed| {
+            let grids = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = WaveFunction::with_seed(ultimaStates(), seed);
+                wf.setPlotting(false).setSize(30, 60).setPropagator(propagator).setHeuristic(Heuristic::Entropy);
+                wf.solve().ok();
+                wf.grid.iter().flatten().map(|ss| format!("{:?}", ss)).collect::<Vec<String>>()
+            });
+            assert_eq!(grids[0], grids[1]);
+        });
+    }
+
+    #[test]
+    fn boundaries_respected_by_both_propagators() {
+        [Boundary::Open, Boundary::Fixed(0)].iter().for_each(|boundary| (0..8).for_each(|seed| {
+            let grids = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = WaveFunction::with_seed(rogueStates(), seed);
+                wf.setPlotting(false).setSize(20, 40).setBoundary(*boundary, *boundary)
+                    .setPropagator(propagator).setBacktracking(100);
+                wf.solve().ok().map(|_| wf.grid.clone())
+            });
+            assert_eq!(grids[0].as_ref().map(|g| format!("{:?}", g)), grids[1].as_ref().map(|g| format!("{:?}", g)));
+            let Some(grid) = &grids[0] else { return };
+            let (h, w) = (grid.len(), grid[0].len());
+            if let Boundary::Fixed(s) = boundary {
+                let allows = |dir: usize, ss: &SuperState| rogueStates()[*s].projections[dir].has(ss.state());
+                assert!((0..w).all(|x| allows(1, &grid[0][x]) && allows(0, &grid[h-1][x])));
+                assert!((0..h).all(|y| allows(2, &grid[y][0]) && allows(3, &grid[y][w-1])));
+            }
+        }));
+    }
+
+    #[test]
+    fn pins_hold_and_impossible_pins_are_undone() {
+        [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| [false, true].iter().for_each(|backtracking| {
+            let mut wf = WaveFunction::with_seed(mazeStates(), 3);
+            wf.setPlotting(false).setSize(20, 40).setPropagator(*propagator);
+            if *backtracking { wf.setBacktracking(100); }
+            let (p, q) = (Point::new(5, 5), Point::new(6, 5));
+            assert_eq!(wf.set(&p, 3), Ok(())); // Vertical wall, so wall below too
+            assert_eq!(wf.ban(&Point::new(10, 10), 0), Ok(()));
+            let before = format!("{:?}", wf.grid);
+            assert_eq!(wf.set(&q, 0), Err(WfcError::Contradiction(q.clone())));
+            assert_eq!(before, format!("{:?}", wf.grid));
+            wf.setBacktracking(100); // maze rarely solves without it
+            assert!(wf.solveRestarting(10).is_ok());
+            assert_eq!(3, wf.stateAt(&p));
+            assert_ne!(0, wf.stateAt(&Point::new(10, 10)));
+        }));
+    }
+
+    #[test]
+    fn count_limits_hold() {
+        let count = |wf: &WaveFunction, s: usize| wf.grid.iter().flatten().filter(|ss| ss.state() == s).count();
+        (0..4).for_each(|seed| {
+            let mut wf = WaveFunction::with_seed(moboStates(), seed);
+            wf.setPlotting(false).setSize(20, 40).setBacktracking(1000).setCount(12, 1, 3);
+            assert!(wf.solveRestarting(10).is_ok());
+            assert!((1..=3).contains(&count(&wf, 12)));
+
+            let mut wf = WaveFunction::with_seed(ultimaStates(), seed);
+            wf.setPlotting(false).setSize(20, 40).setBacktracking(1000).setCount(5, 0, 20).setCount(2, 100, usize::MAX);
+            assert!(wf.solveRestarting(10).is_ok());
+            assert!(count(&wf, 5) <= 20 && 100 <= count(&wf, 2));
+        });
+        let mut wf = WaveFunction::with_seed(ultimaStates(), 0);
+        wf.setPlotting(false).setSize(20, 40).setCount(0, 801, usize::MAX);
+        assert_eq!(wf.solve(), Err(WfcError::CountLimit(0)));
+    }
+
+    // Cells of the passable states, and how many of them one flood fill reaches
+    fn regions(wf: &WaveFunction, passable: &[usize]) -> (usize, usize) {
+        let open = |p: &Point| passable.contains(&wf.stateAt(p));
+        let cells: Vec<Point> = wf.cells().filter(open).collect();
+        let mut seen = std::collections::HashSet::new();
+        let mut stack: Vec<Point> = cells.first().cloned().into_iter().collect();
+        while let Some(p) = stack.pop() {
+            if !seen.insert(p.clone()) { continue }
+            (0..wf.topology.directions()).filter_map(|dir| wf.neighbor(&p, dir))
+                .filter(|q| open(q) && !seen.contains(q)).for_each(|q| stack.push(q));
+        }
+        (cells.len(), seen.len())
+    }
+
+    #[test]
+    fn connected_tilesets_form_one_region() {
+        let passable: Vec<usize> = (1..=12).collect(); // Walls and paths, not empty space
+        (0..4).for_each(|seed| {
+            let mut wf = WaveFunction::with_seed(mazeStates(), seed);
+            wf.setPlotting(false).setSize(20, 40).setBacktracking(1000).setConnected(&passable);
+            assert!(wf.solveRestarting(10).is_ok());
+            let (cells, reached) = regions(&wf, &passable);
+            assert_eq!(cells, reached);
+
+            let mut wf = WaveFunction::with_seed(rogueStates(), seed);
+            wf.setPlotting(false).setSize(20, 40).setBoundary(Boundary::Fixed(0), Boundary::Fixed(0))
+                .setBacktracking(1000).setConnected(&passable);
+            assert!(wf.solveRestarting(10).is_ok());
+            let (cells, reached) = regions(&wf, &passable);
+            assert_eq!(cells, reached);
+        });
+    }
+
+    #[test]
+    fn variants_allow_each_other_both_ways() {
+        // An F tile that may only sit to the right of its own mirror image
+        let f = || variants(&[Tile::new(&[("", "F")], Symmetry::F, &[&[], &[], &[], &[(0, 4)]])]);
+        [mazeStates as fn() -> Vec<State>, maze0States, f].iter().for_each(|states| {
+            let states = states();
+            states.iter().for_each(|a| (0..4).for_each(|dir| states.iter().for_each(|b| {
+                assert_eq!(a.projections[dir].has(b.id), b.projections[Square.opposite(dir)].has(a.id), "{} {} {}", a.id, dir, b.id);
+            })));
+        });
+        let states = f();
+        assert_eq!(8, states.len());
+        assert!(states.iter().all(|s| 1 == (0..4).map(|dir| s.projections[dir].count()).sum::<usize>()));
+    }
+
+    #[test]
+    fn validate_finds_rule_mistakes() {
+        let tilesets = [mazeStates as fn() -> Vec<State>, maze0States, ultimaStates, moboStates, rogueStates, islandStates, dungeonStates, continentStates];
+        tilesets.iter().for_each(|states| assert_eq!(Vec::<RuleError>::new(), validate(&states(), &Square)));
+        assert!(validate(&hexStates(), &Hex).is_empty() && validate(&caveStates(), &Cube).is_empty());
+        let errors = validate(&[
+            State::new(0, ("", "a"), &[&[0], &[0], &[0], &[0, 7]]),
+            State::new(2, ("", "b"), &[&[1], &[1], &[], &[1]]),
+            State::new(2, ("", "b"), &[&[1], &[1], &[], &[1]]),
+            State::new(3, ("", "c"), &[&[3], &[3], &[3]])], &Square);
+        [RuleError::Misnumbered(1, 2), RuleError::Directions(3, 3), RuleError::OutOfRange(0, 3, 7),
+         RuleError::Empty(1, 2), RuleError::Asymmetric(1, 3, 1), RuleError::Duplicate(2, 1)]
+            .iter().for_each(|e| assert!(errors.contains(e), "{} not in {:?}", e, errors));
+
+        let onesided = || vec![
+            State::new(0, ("", "a"), &[&[0, 1], &[0], &[0], &[0]]),
+            State::new(1, ("", "b"), &[&[1], &[1], &[1], &[1]])];
+        assert_eq!(vec![RuleError::Asymmetric(0, 0, 1)], validate(&onesided(), &Square));
+        let mut strict = onesided();
+        assert_eq!(1, symmetrize(&mut strict, &Square, Symmetrize::Strict));
+        assert!(validate(&strict, &Square).is_empty() && !strict[0].projections[0].has(1));
+        let mut loose = onesided();
+        assert_eq!(1, symmetrize(&mut loose, &Square, Symmetrize::Loose));
+        assert!(validate(&loose, &Square).is_empty() && loose[1].projections[1].has(0));
+    }
+
+    // Square's directions clockwise, 0 up 1 right 2 down 3 left, so each
+    // pairs with the one two along
+    struct Clockwise;
+
+    impl Topology for Clockwise {
+        fn directions (&self) -> usize { 4 }
+        fn step (&self, _p: &Point, dir: usize) -> (isize, isize, isize) {
+            [(-1, 0, 0), (0, 1, 0), (1, 0, 0), (0, -1, 0)][dir]
+        }
+        fn opposite (&self, dir: usize) -> usize { (dir + 2) % 4 }
+    }
+
+    #[test]
+    fn topologies_pair_their_own_directions() {
+        // Stripes:  rows of a and b in turn
+        let stripes = || vec![
+            State::new(0, ("", "a"), &[&[1], &[0], &[1], &[0]]),
+            State::new(1, ("", "b"), &[&[0], &[1], &[0], &[1]])];
+        assert!(validate(&stripes(), &Clockwise).is_empty());
+        assert!(!validate(&stripes(), &Square).is_empty());
+        let mut states = stripes();
+        assert_eq!(0, symmetrize(&mut states, &Clockwise, Symmetrize::Strict));
+        [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| {
+            let mut wf = WaveFunction::with_topology(stripes(), Clockwise, 2);
+            wf.setPlotting(false).setSize(6, 10).setPropagator(*propagator);
+            assert!(wf.solveRestarting(10).is_ok());
+            assert!(["aaaaaaaaaa\nbbbbbbbbbb", "bbbbbbbbbb\naaaaaaaaaa"].iter().any(|rows| wf.text().starts_with(rows)));
+            wf.cells().for_each(|p| (0..4).for_each(|dir| {
+                let q = wf.neighbor(&p, dir).unwrap();
+                assert_eq!(Some(p.clone()), wf.neighbor(&q, Clockwise.opposite(dir)));
+                assert!(wf.basestates[wf.stateAt(&p)].projections[dir].has(wf.stateAt(&q)));
+            }));
+        });
+    }
+
+    #[test]
+    fn overlapping_output_is_made_of_sample_patterns() {
+        let states = overlapping(HOUSES, 3, 2, true);
+        assert_eq!(8 * 14 * 2, states.iter().map(|s| s.weight).sum::<f64>() as usize);
+        let grid = sampleGrid(HOUSES);
+        let blocks = |g: &Vec<Vec<char>>| {
+            let (h, w) = (g.len(), g[0].len());
+            (0..h).flat_map(|y| (0..w).map(move |x| (y, x)))
+                .map(|(y, x)| (0..9).map(|i| g[(y + i / 3) % h][(x + i % 3) % w]).collect::<String>())
+                .collect::<std::collections::HashSet<String>>()
+        };
+        let mirrored = grid.iter().map(|r| r.iter().rev().copied().collect()).collect();
+        let sample: std::collections::HashSet<String> = blocks(&grid).union(&blocks(&mirrored)).cloned().collect();
+        (0..4).for_each(|seed| {
+            let mut wf = WaveFunction::with_seed(overlapping(HOUSES, 3, 2, true), seed);
+            wf.setPlotting(false).setSize(20, 40).setBacktracking(1000);
+            assert!(wf.solveRestarting(10).is_ok());
+            let text = sampleGrid(&wf.text());
+            assert!(blocks(&text).is_subset(&sample));
+        });
+    }
+
+    #[test]
+    fn inferred_tileset_from_an_example() {
+        let states = inferred(HOUSES, true);
+        assert_eq!(vec![".", "+", "-", "|", " "], states.iter().map(|s| s.glyph.glyph.as_str()).collect::<Vec<&str>>());
+        assert_eq!(8.0 * 14.0, states.iter().map(|s| s.weight).sum::<f64>());
+        assert!(validate(&states, &Square).is_empty());
+        assert!(states[3].projections[0].has(1) && !states[3].projections[2].has(2)); // + above |, no - right of |
+        let source = tilesetSource("houses", &states);
+        assert!(source.starts_with("pub fn housesStates () -> Vec<State> {\n    vec!(\n"));
+        assert!(source.contains("        State::new(3, (\"\\u{1b}[0m\",\"|\"), &[&[1,3],&[1,3],&[0,4],&[0,4]]).weighted(8.0),\n"));
+        let mut wf = WaveFunction::with_seed(states, 1);
+        wf.setPlotting(false).setSize(20, 40);
+        assert!(wf.solveRestarting(10).is_ok());
+        // The same from a file
+        let path = env::temp_dir().join(format!("houses{}.txt", std::process::id()));
+        fs::write(&path, HOUSES).unwrap();
+        let states = sampleFile(path.to_str().unwrap(), 3).unwrap();
+        assert_eq!(overlapping(HOUSES, 3, 1, true).len(), states.len());
+        assert!(sampleFile(path.to_str().unwrap(), 0).is_err());
+        fs::write(&path, "\n\n").unwrap();
+        assert!(sampleFile(path.to_str().unwrap(), 3).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(sampleFile(path.to_str().unwrap(), 3).is_err());
+    }
+
+    // Only left and right neighbors, so every row is solved on its own
+    struct Rows;
+    impl Topology for Rows {
+        fn directions (&self) -> usize { 2 }
+        fn step (&self, _p: &Point, dir: usize) -> (isize, isize, isize) { [(0, 1, 0), (0, -1, 0)][dir] }
+    }
+
+    #[test]
+    fn solves_on_another_topology() {
+        // a and b alternate along a row
+        let states = || vec![State::new(0, ("", "a"), &[&[1], &[1]]), State::new(1, ("", "b"), &[&[0], &[0]])];
+        [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| {
+            let mut wf = WaveFunction::with_topology(states(), Rows, 1);
+            wf.setPlotting(false).setSize(6, 8).setPropagator(*propagator);
+            assert!(wf.solve().is_ok());
+            let rows: Vec<String> = wf.text().lines().map(|l| l.to_string()).collect();
+            assert!(rows.iter().all(|r| r == "abababab" || r == "babababa"));
+            assert!(rows.iter().any(|r| r != &rows[0]));
+        });
+    }
+
+    #[test]
+    fn hexes_neighbor_each_other_and_follow_the_rules() {
+        [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| {
+            let mut wf = WaveFunction::with_topology(hexStates(), Hex, 2);
+            wf.setPlotting(false).setSize(10, 16).setPropagator(*propagator);
+            assert!(wf.solveRestarting(10).is_ok());
+            (0..10).for_each(|y| (0..16).for_each(|x| (0..6).for_each(|dir| {
+                let p = Point::new(y, x);
+                let q = wf.neighbor(&p, dir).unwrap();
+                assert_eq!(Some(p.clone()), wf.neighbor(&q, Hex.opposite(dir)));
+                assert!(hexStates()[wf.stateAt(&p)].projections[dir].has(wf.stateAt(&q)));
+            })));
+            let text = wf.text();
+            let rows: Vec<&str> = text.lines().collect();
+            assert!(rows[0].starts_with(BLK) && rows[1].starts_with(' '));
+            assert!(rows.iter().all(|r| 32 <= r.chars().count()));
+        });
+        // An odd number of rows only without wrapping
+        let mut wf = WaveFunction::with_topology(hexStates(), Hex, 2);
+        assert_eq!(0, wf.term.h & 1);
+        wf.setPlotting(false).setBoundary(Boundary::Open, Boundary::Wrap).setSize(9, 16);
+        assert!(wf.solveRestarting(10).is_ok());
+    }
+
+    #[test]
+    fn odd_hex_rows_dont_wrap() {
+        // A row is dropped however the rows and topology came together
+        let mut wf = WaveFunction::with_topology(hexStates(), Hex, 2);
+        assert_eq!(24, wf.term.h);
+        assert_eq!((8, 8), (wf.setSize(9, 16).term.h, wf.grid.len()));
+        let mut wf = WaveFunction::with_seed(ultimaStates(), 2);
+        assert_eq!((24, 24), (wf.setTopology(Hex).term.h, wf.grid.len()));
+        wf.setSize(10, 16).setBoundary(Boundary::Open, Boundary::Wrap).setSize(9, 16);
+        assert_eq!(9, wf.term.h);
+        assert_eq!(8, wf.setBoundary(Boundary::Wrap, Boundary::Wrap).term.h);
+        assert!(wf.solveRestarting(10).is_ok());
+    }
+
+    #[test]
+    fn voxels_follow_the_rules_between_layers() {
+        let grids = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+            let mut wf = WaveFunction::with_topology(caveStates(), Cube, 4);
+            wf.setPlotting(false).setSize(8, 12).setLayers(3, Boundary::Fixed(0)).setPropagator(propagator).setBacktracking(100);
+            assert!(wf.solveRestarting(10).is_ok());
+            wf.cells().for_each(|p| (0..6).for_each(|dir| {
+                let Some(q) = wf.neighbor(&p, dir) else {
+                    // Rock below the lowest layer and above the highest
+                    assert!(caveStates()[0].projections[Cube.opposite(dir)].has(wf.stateAt(&p)));
+                    return
+                };
+                assert_eq!(Some(p.clone()), wf.neighbor(&q, Cube.opposite(dir)));
+                assert!(caveStates()[wf.stateAt(&p)].projections[dir].has(wf.stateAt(&q)));
+            }));
+            wf.voxels()
+        });
+        assert_eq!(grids[0], grids[1]);
+        assert!(grids[0].starts_with("3 8 12\n"));
+        assert_eq!(3 * 8 + 2 + 1, grids[0].lines().count());
+    }
+
+    #[test]
+    fn diagonal_rules_hold() {
+        // Water and land meet only across beach, corners included
+        let coast = |diagonal: bool| {
+            let (water, land, beach): (&[usize], &[usize], &[usize]) = (&[0, 2], &[1, 2], &[0, 1, 2]);
+            let states = vec![
+                State::new(0, ("", "~"), &[water; 4]),
+                State::new(1, ("", "#"), &[land; 4]),
+                State::new(2, ("", "."), &[beach; 4]).weighted(0.2)];
+            IF!(diagonal, states.into_iter().zip([water, land, beach]).map(|(s, d)| s.diagonals(&[d; 4])).collect(), states)
+        };
+        let touches = |wf: &WaveFunction| wf.cells().any(|p| (4..8).any(|dir| {
+            let q = wf.neighbor(&p, dir).unwrap();
+            0 == wf.stateAt(&p) && 1 == wf.stateAt(&q)
+        }));
+        (0..4).for_each(|seed| {
+            let grids = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = WaveFunction::with_topology(coast(true), Moore, seed);
+                wf.setPlotting(false).setSize(20, 40).setPropagator(propagator).setBacktracking(100);
+                assert!(wf.solveRestarting(10).is_ok());
+                assert!(!touches(&wf));
+                wf.text()
+            });
+            assert_eq!(grids[0], grids[1]);
+        });
+        // Only the square rules, so corners touch
+        assert!((0..4).any(|seed| {
+            let mut wf = WaveFunction::with_topology(coast(false), Moore, seed);
+            wf.setPlotting(false).setSize(20, 40).setBacktracking(100);
+            assert!(wf.solveRestarting(10).is_ok());
+            touches(&wf)
+        }));
+    }
+
+    #[test]
+    fn big_tiles_are_placed_whole() {
+        // A 2x3 tile in a field of blanks, fitting inside open edges
+        let blank: &[usize] = &[0];
+        let states = || bigTiles(vec![State::new(0, ("", "."), &[blank; 4])],
+            &[BigTile::new("", &["abc", "def"], &[blank; 4])]);
+        let whole = |wf: &WaveFunction, p: &Point| {
+            let s = wf.stateAt(p) - 1;
+            let corner = (p.y as isize - (s / 3) as isize, p.x as isize - (s % 3) as isize);
+            (0..6).all(|t| {
+                let (y, x) = (corner.0 + (t / 3) as isize, corner.1 + (t % 3) as isize);
+                (0..10).contains(&y) && (0..12).contains(&x) && 1 + t == wf.stateAt(&Point::new(y as usize, x as usize))
+            })
+        };
+        (0..4).for_each(|seed| {
+            let grids = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = WaveFunction::with_seed(states(), seed);
+                wf.setPlotting(false).setSize(10, 12).setBoundary(Boundary::Open, Boundary::Open).setPropagator(propagator);
+                assert!(wf.solve().is_ok());
+                assert!(wf.cells().filter(|p| 0 < wf.stateAt(p)).all(|p| whole(&wf, &p)));
+                wf.text()
+            });
+            assert_eq!(grids[0], grids[1]);
+            assert!(grids[0].contains("abc"));
+        });
+        // One cell of the footprint fixes all of it
+        let mut wf = WaveFunction::with_seed(states(), 0);
+        wf.setPlotting(false).setSize(10, 12).setBoundary(Boundary::Open, Boundary::Open);
+        assert_eq!(wf.set(&Point::new(5, 5), 5), Ok(()));
+        assert!(whole(&wf, &Point::new(5, 5)));
+        // Nor can it hang past the edge
+        assert_eq!(wf.set(&Point::new(9, 0), 1), Err(WfcError::Contradiction(Point::new(9, 0))));
+    }
+
+    #[test]
+    fn refined_cells_keep_to_their_zones() {
+        let zones: [&[usize]; 3] = [&[0, 1], &[2, 3], &[4, 5]];
+        (0..4).for_each(|seed| {
+            let mut coarse = WaveFunction::with_seed(continentStates(), seed);
+            coarse.setPlotting(false).setSize(6, 10);
+            assert!(coarse.solve().is_ok());
+            let mut wf = WaveFunction::with_seed(ultimaStates(), seed);
+            wf.setPlotting(false).setBacktracking(1000).refine(&coarse, 4, &zones, 1);
+            assert!(wf.solveRestarting(10).is_ok());
+            assert_eq!((24, 40), (wf.term.h, wf.term.w));
+            // Its own zone, or within a cell of a neighboring one
+            wf.cells().for_each(|p| {
+                let state = wf.stateAt(&p);
+                let zone = |y: usize, x: usize| zones[coarse.stateAt(&Point::new(y % 24 / 4, x % 40 / 4))].contains(&state);
+                assert!(zone(p.y, p.x) || [(23, 39), (23, 0), (23, 1), (0, 39), (0, 1), (1, 39), (1, 0), (1, 1)]
+                    .iter().any(|(dy, dx)| zone(p.y + dy, p.x + dx)), "{:?} {}", p, state);
+            });
+        });
+    }
+
+    #[test]
+    fn chunks_fit_whatever_the_order() {
+        let (h, w) = (10, 16);
+        // Rooms kept off the edges, or they'd leave chunks that can't be made,
+        // with passages crossing them
+        let ultima = &[0,1,2,3,4,5][..];
+        [(ultimaStates as fn() -> Vec<State>, ultima, ultima, 1), (rogueStates, &[0,10], &[0,11], 4), (dungeonStates, &[0,1], &[0,2], 4)].iter().for_each(|(tileset, across, down, gap)| {
+            let mut forward = World::new(*tileset, 7, h, w);
+            let mut backward = World::new(*tileset, 7, h, w);
+            forward.setSeams(across, down, *gap);
+            backward.setSeams(across, down, *gap);
+            let places: Vec<(isize, isize)> = (-1..2).flat_map(|cy| (-1..2).map(move |cx| (cx, cy))).collect();
+            places.iter().for_each(|(cx, cy)| assert!(forward.chunk(*cx, *cy).is_ok(), "({},{})", cx, cy));
+            places.iter().rev().for_each(|(cx, cy)| assert!(backward.chunk(*cx, *cy).is_ok(), "({},{})", cx, cy));
+            assert_eq!(forward.chunks, backward.chunks);
+            // All nine side by side follow the rules, across the edges too
+            let map: Vec<Vec<usize>> = (0..3 * h).map(|y| (0..3 * w).map(|x| {
+                forward.chunks[&((x / w) as isize - 1, (y / h) as isize - 1)][y % h][x % w]
+            }).collect()).collect();
+            let states = tileset();
+            (0..3 * h).for_each(|y| (0..3 * w).for_each(|x| {
+                if y + 1 < 3 * h { assert!(states[map[y][x]].projections[1].has(map[y + 1][x]), "({},{}) down", y, x); }
+                if x + 1 < 3 * w { assert!(states[map[y][x]].projections[2].has(map[y][x + 1]), "({},{}) right", y, x); }
+            }));
+            // Something besides rock or deep water
+            assert!(map.iter().flatten().filter(|s| **s != 0).count() > h * w);
+            let chunk = forward.chunks[&(1, 1)].clone();
+            assert_eq!(&chunk, forward.forget(1, 1).forget(0, 1).chunk(1, 1).unwrap());
+            let mut other = World::new(*tileset, 8, h, w);
+            other.setSeams(across, down, *gap);
+            assert_ne!(forward.chunks[&(0, 0)], other.chunk(0, 0).unwrap().clone());
+        });
+    }
+
+    #[test]
+    fn passages_cross_chunk_edges() {
+        // Without seams a room on this chunk's left edge can't be finished
+        assert!(World::new(rogueStates, 1, 10, 16).chunk(0, 1).is_err());
+        let (h, w) = (10, 16);
+        [(rogueStates as fn() -> Vec<State>, [0,10], [0,11], [10,11,12]), (dungeonStates, [0,1], [0,2], [1,2,3])].iter().for_each(|(tileset, across, down, passages)| {
+            let mut world = World::new(*tileset, 1, h, w);
+            world.setSeams(across, down, 4);
+            (0..3).for_each(|cy| (0..3).for_each(|cx| assert!(world.chunk(cx, cy).is_ok(), "({},{})", cx, cy)));
+            let map: Vec<Vec<usize>> = (0..3 * h).map(|y| (0..3 * w).map(|x| {
+                world.chunks[&((x / w) as isize, (y / h) as isize)][y % h][x % w]
+            }).collect()).collect();
+            let passage = |y: usize, x: usize| passages.contains(&map[y][x]);
+            // A passage on an edge, and carrying on into the chunks either side
+            let crossings = (1..3 * h - 1).flat_map(|y| (1..3 * w - 1).map(move |x| (y, x))).filter(|(y, x)| passage(*y, *x) && (
+                0 == y % h && passage(y - 1, *x) && passage(y + 1, *x) || 0 == x % w && passage(*y, x - 1) && passage(*y, x + 1)));
+            assert!(0 < crossings.count());
+        });
+    }
+
+    #[test]
+    fn scrolled_rows_follow_the_rows_above() {
+        let follows = |wf: &WaveFunction| wf.cells().all(|p| (0..4).all(|dir| wf.neighbor(&p, dir).is_none_or(|q| {
+            wf.basestates[wf.stateAt(&p)].projections[dir].has(wf.stateAt(&q))
+        })));
+        // Maze wraps, ultima wraps and rogue has rock all around
+        let (maze, ultima, rogue) = (0, 1, 2);
+        [(mazeStates as fn() -> Vec<State>, maze), (ultimaStates, ultima), (rogueStates, rogue)].iter().for_each(|(states, kind)| {
+            let texts = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = WaveFunction::with_seed(states(), 3);
+                wf.setPlotting(false).setSize(12, 30).setPropagator(propagator);
+                if maze == *kind { wf.setBacktracking(1000).setConnected(&[1,2,3,4,5,6,7,8,9,10,11,12]); }
+                if rogue == *kind { wf.setBoundary(Boundary::Fixed(0), Boundary::Fixed(0)).setBacktracking(1000); }
+                assert!(wf.solveRestarting(10).is_ok());
+                (0..40).for_each(|_| {
+                    let before = wf.text();
+                    assert!(wf.scrollRow(10).is_ok());
+                    let after = wf.text();
+                    // Everything moved up a row, a new one below
+                    assert_eq!(before.lines().skip(1).collect::<Vec<_>>(), after.lines().take(11).collect::<Vec<_>>());
+                    assert!(!after.contains(['*', '!']));
+                    assert!(follows(&wf));
+                });
+                // Rooms keep coming, not just rock and passages squeezed
+                // between the rows above and the rock below
+                if rogue == *kind { assert!(wf.cells().filter(|p| (1..10).contains(&wf.stateAt(p))).count() > 12 * 30 / 10); }
+                wf.text()
+            });
+            assert_eq!(texts[0], texts[1]);
+        });
+    }
+
+    #[test]
+    fn panning_keeps_only_the_view() {
+        let follows = |wf: &WaveFunction| wf.cells().all(|p| (0..wf.topology.directions()).all(|dir| wf.neighbor(&p, dir).is_none_or(|q| {
+            wf.basestates[wf.stateAt(&p)].projections[dir].has(wf.stateAt(&q))
+        })));
+        // A few right and down and straight back, then right, down and back
+        // past the start, up, then left
+        let path = [(2, 3), (1, 3), (0, 3), (3, 3), (2, 20), (1, 15), (3, 45), (0, 25), (3, 10)];
+        // Ultima and hexes wrap, rogue has rock all around
+        let (ultima, hex, rogue) = (0, 1, 2);
+        [ultima, hex, rogue].iter().for_each(|kind| {
+            let texts = [Propagator::Projection, Propagator::Ac4].map(|propagator| {
+                let mut wf = IF!(hex == *kind, WaveFunction::with_topology(hexStates(), Hex, 5),
+                    WaveFunction::with_seed(IF!(rogue == *kind, rogueStates(), ultimaStates()), 5));
+                wf.setPlotting(false).setSize(12, 30).setPropagator(propagator).setBacktracking(100);
+                if rogue == *kind { wf.setBoundary(Boundary::Fixed(0), Boundary::Fixed(0)); }
+                assert!(wf.solveRestarting(10).is_ok());
+                path.iter().for_each(|(dir, steps)| (0..*steps).for_each(|_| {
+                    let before = wf.text();
+                    assert!(wf.pan(*dir, 10).is_ok());
+                    let after = wf.text();
+                    if 2 == *dir && hex != *kind {
+                        // Everything moved left a column, a new one on the right
+                        before.lines().zip(after.lines()).for_each(|(b, a)| assert!(b.chars().skip(1).eq(a.chars().take(29))));
+                    }
+                    assert!(!after.contains(['*', '!']));
+                    assert!(follows(&wf));
+                }));
+                assert_eq!((wf.view, wf.grid.len(), wf.grid[0].len()), ((-10, -35), 12, 30));
+                wf.text()
+            });
+            assert_eq!(texts[0], texts[1]);
+        });
+    }
+
+    // cargo test --release bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_tilesets_500x500() {
+        let tilesets = [
+            ("maze", mazeStates as fn() -> Vec<State>), ("maze0", maze0States), ("ultima", ultimaStates),
+            ("mobo", moboStates), ("rogue", rogueStates)];
+        tilesets.iter().for_each(|(name, states)| {
+            print!("{:8}", name);
+            [Propagator::Projection, Propagator::Ac4].iter().for_each(|propagator| {
+                let mut wf = WaveFunction::with_seed(states(), 1);
+                wf.setPlotting(false).setSize(500, 500).setPropagator(*propagator);
+                let start = std::time::Instant::now();
+                while wf.collapseMaybe() { }
+                print!(" {:?} {:.3}s", propagator, start.elapsed().as_secs_f64());
+            });
+            println!();
+        });
+    }
+}
+